@@ -0,0 +1,224 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ports::PortSpec;
+use crate::scanner::{ScanOptions, WorkItem};
+use crate::service_info::ServiceInfo;
+use crate::targets::ResolvedTarget;
+
+/// How often a running scan writes its checkpoint
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Format of the checkpoints this version writes. Bump it whenever a change to the
+/// format cannot be read by older versions, or needs more than a serde default to
+/// read older checkpoints. Checkpoints from before versioning count as version 0.
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+/// Everything needed to continue a scan: the resolved targets and options it
+/// was started with, the work items not yet probed and the results so far
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    #[serde(default)]
+    pub version: u32,
+    pub targets: Vec<ResolvedTarget>,
+    /// The ports probed on targets without a pinned port; `None` in checkpoints
+    /// written before it was recorded
+    #[serde(default)]
+    pub ports: Option<PortSpec>,
+    pub options: ScanOptions,
+    /// Number of probes in the whole scan, including those already done
+    pub total_probes: usize,
+    pub remaining: Vec<WorkItem>,
+    pub results: Vec<ServiceInfo>,
+}
+
+impl Checkpoint {
+    /// Number of probes already done, for seeding the progress counter
+    pub fn completed_probes(&self) -> usize {
+        let remaining: usize = self.remaining.iter().map(|i| usize::from(i.tcp) + usize::from(i.udp)).sum();
+        self.total_probes.saturating_sub(remaining)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let checkpoint: Checkpoint =
+            serde_json::from_str(&data).map_err(|e| format!("{}: not a valid checkpoint: {}", path.display(), e))?;
+        if checkpoint.version > CHECKPOINT_VERSION {
+            return Err(format!(
+                "{}: checkpoint format {} is newer than this version understands ({})",
+                path.display(),
+                checkpoint.version,
+                CHECKPOINT_VERSION
+            ));
+        }
+        Ok(checkpoint)
+    }
+
+    /// Writes the checkpoint through a temporary file, so a crash mid-write
+    /// never leaves a truncated checkpoint behind
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let data = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, data).map_err(|e| format!("{}: {}", Path::new(&tmp).display(), e))?;
+        fs::rename(&tmp, path).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Tracks which work items of a running scan are done, so the checkpoint can be
+/// written at any time. Work items are identified by their index in `remaining`
+/// of the checkpoint the scan was started from, which is also the order the
+/// engine receives them in.
+#[derive(Debug)]
+pub struct Checkpointer {
+    path: PathBuf,
+    state: Mutex<CheckpointState>,
+}
+
+#[derive(Debug)]
+struct CheckpointState {
+    base: Checkpoint,
+    done: Vec<bool>,
+    results: Vec<ServiceInfo>,
+}
+
+impl Checkpointer {
+    pub fn new(path: PathBuf, base: Checkpoint) -> Self {
+        let done = vec![false; base.remaining.len()];
+        Self {
+            path,
+            state: Mutex::new(CheckpointState { base, done, results: Vec::new() }),
+        }
+    }
+
+    /// Marks a work item as fully probed along with every result it produced.
+    /// Both are recorded under one lock so a checkpoint never holds a result
+    /// for an item it still lists as remaining.
+    pub fn record(&self, index: usize, results: Vec<ServiceInfo>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(done) = state.done.get_mut(index) {
+            *done = true;
+            state.results.extend(results);
+        }
+    }
+
+    pub fn snapshot(&self) -> Checkpoint {
+        let state = self.state.lock().unwrap();
+        let remaining = state
+            .base
+            .remaining
+            .iter()
+            .zip(&state.done)
+            .filter(|(_, done)| !**done)
+            .map(|(item, _)| item.clone())
+            .collect();
+        let mut results = state.base.results.clone();
+        results.extend(state.results.iter().cloned());
+        Checkpoint {
+            version: CHECKPOINT_VERSION,
+            targets: state.base.targets.clone(),
+            ports: state.base.ports.clone(),
+            options: state.base.options.clone(),
+            total_probes: state.base.total_probes,
+            remaining,
+            results,
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        self.snapshot().save(&self.path)
+    }
+
+    /// Saves the checkpoint every `CHECKPOINT_INTERVAL` until the returned task is aborted
+    pub fn spawn_writer(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let checkpointer = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHECKPOINT_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let snapshot = checkpointer.snapshot();
+                let path = checkpointer.path.clone();
+                // Serializing a large scan takes a while, so keep it off the async workers
+                let saved = tokio::task::spawn_blocking(move || snapshot.save(&path)).await;
+                if let Ok(Err(e)) = saved {
+                    eprintln!("Warning: failed to write checkpoint: {}", e);
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service_info::{PortState, Protocol};
+    use crate::timing::Timing;
+
+    #[test]
+    fn test_checkpoint_round_trip_skips_done_items() {
+        let target = ResolvedTarget { host: "localhost".to_string(), ip: "127.0.0.1".parse().unwrap(), port: None };
+        let item = |port| WorkItem {
+            host: Arc::from("localhost"),
+            addr: format!("127.0.0.1:{}", port).parse().unwrap(),
+            tcp: true,
+            udp: port == 53,
+        };
+        let earlier = ServiceInfo::new("localhost".to_string(), 21, Protocol::TCP, PortState::Open);
+        let base = Checkpoint {
+            version: CHECKPOINT_VERSION,
+            targets: vec![target],
+            ports: None,
+            options: ScanOptions { max_per_host: Some(4), ..ScanOptions::default() },
+            total_probes: 5,
+            remaining: vec![item(22), item(53), item(80)],
+            results: vec![earlier],
+        };
+        assert_eq!(base.completed_probes(), 1);
+
+        let path = std::env::temp_dir().join(format!("port-scanner-checkpoint-{}.json", std::process::id()));
+        let checkpointer = Checkpointer::new(path.clone(), base);
+        let open = ServiceInfo::new("localhost".to_string(), 22, Protocol::TCP, PortState::Open);
+        checkpointer.record(0, vec![open]);
+        checkpointer.record(2, Vec::new());
+        checkpointer.save().unwrap();
+
+        let loaded = Checkpoint::load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded.remaining.len(), 1);
+        assert_eq!(loaded.remaining[0].addr.port(), 53);
+        assert!(loaded.remaining[0].udp);
+        assert_eq!(loaded.results.iter().map(|r| r.port).collect::<Vec<_>>(), vec![21, 22]);
+        assert_eq!(loaded.completed_probes(), 3);
+        assert_eq!(loaded.options.max_per_host, Some(4));
+        assert_eq!(loaded.options.timing, Timing::default());
+        assert_eq!(loaded.version, CHECKPOINT_VERSION);
+    }
+
+    #[test]
+    fn test_checkpoint_versions() {
+        let path = std::env::temp_dir().join(format!("port-scanner-checkpoint-version-{}.json", std::process::id()));
+        let checkpoint = |version: Option<u32>| {
+            let version = version.map(|v| format!(r#""version": {}, "#, v)).unwrap_or_default();
+            format!(
+                r#"{{ {}"targets": [], "total_probes": 0, "remaining": [], "results": [],
+                "options": {{ "timing": {}, "max_per_host": null, "show_closed": false }} }}"#,
+                version,
+                serde_json::to_string(&Timing::default()).unwrap()
+            )
+        };
+
+        // Checkpoints from before versioning still load
+        fs::write(&path, checkpoint(None)).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap().version, 0);
+
+        fs::write(&path, checkpoint(Some(CHECKPOINT_VERSION + 1))).unwrap();
+        let err = Checkpoint::load(&path).unwrap_err();
+        let _ = fs::remove_file(&path);
+        assert!(err.contains("is newer than this version understands"), "{}", err);
+    }
+}
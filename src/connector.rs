@@ -0,0 +1,212 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+
+use crate::proxy::Proxy;
+
+/// How long `validate` waits for the proxy to accept a connection and for each
+/// step of its handshake
+const PROXY_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Opens the sockets of every probe: port checks, host discovery pings and
+/// fingerprinting connections. Multi-homed scanners use it to send from a chosen
+/// local address or network device; the default leaves both to the routing table.
+/// With a proxy, TCP connections are tunnelled through it instead, and UDP and raw
+/// socket probes are not available.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Connector {
+    /// Local address probes are sent from
+    pub source_ip: Option<IpAddr>,
+    /// Network device (e.g. `eth0.20`) probes are bound to with `SO_BINDTODEVICE`, Linux only
+    pub interface: Option<String>,
+    /// SOCKS5 or HTTP CONNECT proxy every TCP connection goes through; the source
+    /// address and interface then apply to the connection to the proxy
+    #[serde(default)]
+    pub proxy: Option<Proxy>,
+}
+
+impl Connector {
+    /// Checks that the source address is local and the interface exists, by
+    /// binding a throwaway socket the way the probes will, and that the proxy
+    /// accepts the credentials
+    pub fn validate(&self) -> Result<(), String> {
+        #[cfg(not(target_os = "linux"))]
+        if self.interface.is_some() {
+            return Err("Binding to an interface is only supported on Linux".to_string());
+        }
+        let domain = match self.source_ip {
+            Some(IpAddr::V6(_)) => Domain::IPV6,
+            _ => Domain::IPV4,
+        };
+        let socket = Socket::new(domain, Type::DGRAM, None).map_err(|e| e.to_string())?;
+        if let Some(ref interface) = self.interface {
+            self.bind_device(&SockRef::from(&socket))
+                .map_err(|e| format!("Cannot bind to interface {}: {}", interface, e))?;
+        }
+        if let Some(source) = self.source_ip {
+            socket
+                .bind(&SocketAddr::new(source, 0).into())
+                .map_err(|e| format!("Cannot send from {}: {}", source, e))?;
+        }
+        if let Some(ref proxy) = self.proxy {
+            if !self.can_reach(proxy.addr.ip()) {
+                return Err(format!("Source address {} cannot reach proxy {}", self.source_ip.unwrap(), proxy));
+            }
+            let mut stream = self
+                .socket(proxy.addr, Type::STREAM, Protocol::TCP)
+                .and_then(|socket| socket.connect_timeout(&proxy.addr.into(), PROXY_CHECK_TIMEOUT).map(|()| socket))
+                .map(TcpStream::from)
+                .map_err(|e| format!("Cannot reach proxy {}: {}", proxy, e))?;
+            stream
+                .set_read_timeout(Some(PROXY_CHECK_TIMEOUT))
+                .and_then(|()| stream.set_write_timeout(Some(PROXY_CHECK_TIMEOUT)))
+                .and_then(|()| proxy.check(&mut stream))
+                .map_err(|e| format!("Proxy {} cannot be used: {}", proxy, e))?;
+        }
+        Ok(())
+    }
+
+    /// Whether probes to `ip` can be sent from the source address; through a proxy
+    /// that is up to the proxy
+    pub fn can_reach(&self, ip: IpAddr) -> bool {
+        self.proxy.is_some() || self.source_ip.is_none_or(|source| source.is_ipv6() == ip.is_ipv6())
+    }
+
+    /// Connects a blocking TCP stream, as the fingerprinting probes use. Through a
+    /// proxy, `timeout` applies to reaching the proxy and to each step of its handshake.
+    pub fn connect_timeout(&self, addr: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+        let Some(ref proxy) = self.proxy else {
+            let socket = self.socket(addr, Type::STREAM, Protocol::TCP)?;
+            socket.connect_timeout(&addr.into(), timeout)?;
+            return Ok(socket.into());
+        };
+        let socket = self.socket(proxy.addr, Type::STREAM, Protocol::TCP)?;
+        socket.connect_timeout(&proxy.addr.into(), timeout).map_err(|e| unreachable_proxy(proxy, e))?;
+        let mut stream = TcpStream::from(socket);
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        proxy.tunnel(&mut stream, addr)?;
+        stream.set_read_timeout(None)?;
+        stream.set_write_timeout(None)?;
+        Ok(stream)
+    }
+
+    /// Connects a TCP stream on the tokio runtime; the caller applies the timeout
+    pub async fn connect(&self, addr: SocketAddr) -> io::Result<tokio::net::TcpStream> {
+        let target = self.proxy.as_ref().map_or(addr, |proxy| proxy.addr);
+        let socket = self.socket(target, Type::STREAM, Protocol::TCP)?;
+        socket.set_nonblocking(true)?;
+        let connect = tokio::net::TcpSocket::from_std_stream(socket.into()).connect(target);
+        let Some(ref proxy) = self.proxy else {
+            return connect.await;
+        };
+        let mut stream = connect.await.map_err(|e| unreachable_proxy(proxy, e))?;
+        proxy.tunnel_async(&mut stream, addr).await?;
+        Ok(stream)
+    }
+
+    /// A UDP socket bound to the source address (or any address) for probing `addr`.
+    /// Fails with `Unsupported` when probes go through a proxy.
+    pub fn udp_socket(&self, addr: SocketAddr) -> io::Result<UdpSocket> {
+        if let Some(ref proxy) = self.proxy {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, format!("UDP cannot be sent through proxy {}", proxy)));
+        }
+        let socket = self.socket(addr, Type::DGRAM, Protocol::UDP)?;
+        if self.source_ip.is_none() {
+            let any = match addr {
+                SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+                SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+            };
+            socket.bind(&any.into())?;
+        }
+        Ok(socket.into())
+    }
+
+    /// Binds an already opened socket for probing addresses of the given family,
+    /// e.g. a raw or ICMP socket
+    pub fn bind_socket(&self, socket: SockRef, v6: bool) -> io::Result<()> {
+        self.bind_device(&socket)?;
+        match self.source_ip {
+            Some(source) if source.is_ipv6() == v6 => socket.bind(&SocketAddr::new(source, 0).into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn socket(&self, addr: SocketAddr, ty: Type, protocol: Protocol) -> io::Result<Socket> {
+        if self.source_ip.is_some_and(|source| source.is_ipv6() != addr.is_ipv6()) {
+            return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "source address is of another family"));
+        }
+        let socket = Socket::new(Domain::for_address(addr), ty, Some(protocol))?;
+        self.bind_socket(SockRef::from(&socket), addr.is_ipv6())?;
+        Ok(socket)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn bind_device(&self, socket: &SockRef) -> io::Result<()> {
+        match self.interface {
+            Some(ref interface) => socket.bind_device(Some(interface.as_bytes())),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn bind_device(&self, _socket: &SockRef) -> io::Result<()> {
+        match self.interface {
+            Some(_) => Err(io::ErrorKind::Unsupported.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Failing to reach the proxy says nothing about the target, so it must not look
+/// like the target refusing or ignoring the connection
+fn unreachable_proxy(proxy: &Proxy, e: io::Error) -> io::Error {
+    io::Error::other(format!("cannot reach proxy {}: {}", proxy, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_binds_source_address_and_interface() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connector = Connector { source_ip: Some("127.0.0.2".parse().unwrap()), ..Default::default() };
+        connector.validate().unwrap();
+
+        connector.connect_timeout(addr, Duration::from_secs(1)).unwrap();
+        let (_, peer) = listener.accept().unwrap();
+        assert_eq!(peer.ip(), connector.source_ip.unwrap());
+
+        let socket = connector.udp_socket(addr).unwrap();
+        assert_eq!(socket.local_addr().unwrap().ip(), connector.source_ip.unwrap());
+
+        // Another family cannot be reached from this source
+        assert!(!connector.can_reach("::1".parse().unwrap()));
+        assert!(connector.connect_timeout("[::1]:22".parse().unwrap(), Duration::from_secs(1)).is_err());
+
+        let connector = Connector { source_ip: Some("203.0.113.7".parse().unwrap()), ..Default::default() };
+        assert!(connector.validate().unwrap_err().starts_with("Cannot send from 203.0.113.7"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_connects_through_interface() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connector = Connector { interface: Some("lo".to_string()), ..Default::default() };
+        // Binding to a device needs CAP_NET_RAW on kernels before 5.7
+        if connector.validate().is_err() {
+            return;
+        }
+        connector.connect(addr).await.unwrap();
+
+        let connector = Connector { interface: Some("no-such-if0".to_string()), ..Default::default() };
+        assert!(connector.validate().unwrap_err().starts_with("Cannot bind to interface no-such-if0"));
+    }
+}
@@ -0,0 +1,281 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+use crate::connector::Connector;
+use crate::scanner::{scan_tcp, TcpResult};
+use crate::timing::Timing;
+use crate::udp::{scan_udp, UdpResult};
+
+/// Which pings decide whether a host is up before its ports are scanned
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiscoveryOptions {
+    /// TCP ports to connect to; a SYN/ACK or a RST means the host is up
+    pub tcp_ports: Vec<u16>,
+    /// UDP ports to send a probe to; a reply or a port-unreachable means the host is up
+    pub udp_ports: Vec<u16>,
+    /// Also send an ICMP echo request, where the system allows it
+    pub icmp: bool,
+}
+
+impl Default for DiscoveryOptions {
+    fn default() -> Self {
+        DiscoveryOptions { tcp_ports: vec![22, 80, 443], udp_ports: vec![53], icmp: true }
+    }
+}
+
+impl DiscoveryOptions {
+    /// Number of pings sent to each host
+    pub fn ping_count(&self) -> usize {
+        self.tcp_ports.len() + self.udp_ports.len() + usize::from(self.icmp)
+    }
+}
+
+/// Whether a host answered discovery, and how
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostStatus {
+    pub host: String,
+    pub ip: IpAddr,
+    pub up: bool,
+    /// The first answer that proved the host up, e.g. `syn-ack (tcp/443)`, or why
+    /// it is considered down
+    pub reason: String,
+    /// Round trip of that answer
+    pub latency_ms: Option<f64>,
+}
+
+impl std::fmt::Display for HostStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let state = if self.up { "up" } else { "down" };
+        write!(f, "{} ({}) is {}: {}", self.host, self.ip, state, self.reason)?;
+        if let Some(latency) = self.latency_ms {
+            write!(f, ", {:.1}ms", latency)?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of one ping: `Ok` with the reason if the host answered, `Err` with the
+/// reason it did not
+type PingResult = Result<(String, Duration), Option<String>>;
+
+/// Pings the host on every configured port at once and reports it up on the first
+/// answer. TCP pings wait `timing.timeout_ms`, UDP pings `timing.udp_timeout_ms`.
+/// A host that only sent back ICMP host or network unreachable errors, or nothing
+/// at all, is down. Every ping is sent through `connector`.
+pub async fn discover_host(
+    host: &str,
+    ip: IpAddr,
+    options: &DiscoveryOptions,
+    timing: &Timing,
+    connector: &Connector,
+) -> HostStatus {
+    let mut pings: JoinSet<PingResult> = JoinSet::new();
+    for &port in &options.tcp_ports {
+        let (timeout_ms, connector) = (timing.timeout_ms, connector.clone());
+        pings.spawn(async move {
+            let started = Instant::now();
+            match scan_tcp(SocketAddr::new(ip, port), timeout_ms, &connector).await {
+                result @ (TcpResult::Open | TcpResult::Closed) => {
+                    Ok((format!("{} (tcp/{})", result.reason(), port), started.elapsed()))
+                }
+                TcpResult::Unreachable(kind) => Err(Some(TcpResult::Unreachable(kind).reason())),
+                TcpResult::Filtered => Err(None),
+            }
+        });
+    }
+    for &port in &options.udp_ports {
+        let (timeout_ms, connector) = (timing.udp_timeout_ms, connector.clone());
+        pings.spawn(async move {
+            let started = Instant::now();
+            match scan_udp(SocketAddr::new(ip, port), timeout_ms, &connector).await {
+                result @ (UdpResult::Open(_) | UdpResult::Closed) => {
+                    Ok((format!("{} (udp/{})", result.reason(), port), started.elapsed()))
+                }
+                result @ UdpResult::HostUnreachable(_) => Err(Some(result.reason())),
+                _ => Err(None),
+            }
+        });
+    }
+    if options.icmp {
+        let (timeout, connector) = (Duration::from_millis(timing.timeout_ms), connector.clone());
+        pings.spawn(async move {
+            let started = Instant::now();
+            match icmp::echo(ip, timeout, &connector).await {
+                Ok(true) => Ok(("echo-reply".to_string(), started.elapsed())),
+                // Not answering, or not being allowed to ask, proves nothing
+                Ok(false) | Err(_) => Err(None),
+            }
+        });
+    }
+
+    let mut down_reason = None;
+    while let Some(ping) = pings.join_next().await {
+        match ping {
+            Ok(Ok((reason, latency))) => {
+                return HostStatus {
+                    host: host.to_string(),
+                    ip,
+                    up: true,
+                    reason,
+                    latency_ms: Some(latency.as_secs_f64() * 1000.0),
+                };
+            }
+            Ok(Err(Some(reason))) => down_reason = down_reason.or(Some(reason)),
+            _ => {}
+        }
+    }
+    HostStatus {
+        host: host.to_string(),
+        ip,
+        up: false,
+        reason: down_reason.unwrap_or_else(|| "no-response".to_string()),
+        latency_ms: None,
+    }
+}
+
+/// ICMP echo over an unprivileged ping socket, falling back to a raw socket
+#[cfg(target_os = "linux")]
+mod icmp {
+    use std::io;
+    use std::net::{IpAddr, SocketAddr, UdpSocket};
+    use std::os::fd::FromRawFd;
+    use std::sync::atomic::{AtomicU16, Ordering};
+    use std::time::Duration;
+
+    use socket2::SockRef;
+
+    use crate::connector::Connector;
+    use crate::util::internet_checksum;
+
+    const ECHO_REQUEST_V4: u8 = 8;
+    const ECHO_REPLY_V4: u8 = 0;
+    const ECHO_REQUEST_V6: u8 = 128;
+    const ECHO_REPLY_V6: u8 = 129;
+
+    static SEQUENCE: AtomicU16 = AtomicU16::new(1);
+
+    /// Sends one echo request and waits for the reply. Returns `Ok(false)` on a
+    /// timeout and an error if the system does not allow ICMP sockets.
+    pub async fn echo(ip: IpAddr, timeout: Duration, connector: &Connector) -> io::Result<bool> {
+        let (socket, raw) = open(ip.is_ipv6())?;
+        connector.bind_socket(SockRef::from(&socket), ip.is_ipv6())?;
+        socket.set_nonblocking(true)?;
+        let socket = tokio::net::UdpSocket::from_std(socket)?;
+        socket.connect(SocketAddr::new(ip, 0)).await?;
+
+        // Ping sockets replace the identifier with their own and only deliver
+        // matching replies; raw sockets see every reply from the host
+        let id = std::process::id() as u16;
+        let seq = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        let request_type = if ip.is_ipv6() { ECHO_REQUEST_V6 } else { ECHO_REQUEST_V4 };
+        let mut request = [0u8; 16];
+        request[0] = request_type;
+        request[4..6].copy_from_slice(&id.to_be_bytes());
+        request[6..8].copy_from_slice(&seq.to_be_bytes());
+        request[8..].copy_from_slice(b"portscan");
+        // The kernel fills in the ICMPv6 checksum, which covers the addresses
+        if !ip.is_ipv6() {
+            let checksum = internet_checksum(&[&request]);
+            request[2..4].copy_from_slice(&checksum.to_be_bytes());
+        }
+        socket.send(&request).await?;
+
+        let wait_reply = async {
+            let mut buf = [0u8; 1500];
+            loop {
+                let len = socket.recv(&mut buf).await?;
+                if is_reply(&buf[..len], ip.is_ipv6(), raw, id, seq) {
+                    return Ok::<_, io::Error>(true);
+                }
+            }
+        };
+        match tokio::time::timeout(timeout, wait_reply).await {
+            Ok(result) => result,
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Whether `packet` is the reply to our request. Raw IPv4 sockets include
+    /// the IP header, and only replies on raw sockets need their identifier checked.
+    fn is_reply(packet: &[u8], v6: bool, raw: bool, id: u16, seq: u16) -> bool {
+        let icmp = if raw && !v6 {
+            let header_len = usize::from(packet.first().map_or(0, |b| b & 0x0f)) * 4;
+            packet.get(header_len..).unwrap_or_default()
+        } else {
+            packet
+        };
+        if icmp.len() < 8 {
+            return false;
+        }
+        let reply_type = if v6 { ECHO_REPLY_V6 } else { ECHO_REPLY_V4 };
+        icmp[0] == reply_type
+            && u16::from_be_bytes([icmp[6], icmp[7]]) == seq
+            && (!raw || u16::from_be_bytes([icmp[4], icmp[5]]) == id)
+    }
+
+    /// Opens a ping socket if `net.ipv4.ping_group_range` allows it, else a raw
+    /// socket. Both are datagram-like, so std's UDP socket drives them.
+    fn open(v6: bool) -> io::Result<(UdpSocket, bool)> {
+        let (domain, protocol) = if v6 {
+            (libc::AF_INET6, libc::IPPROTO_ICMPV6)
+        } else {
+            (libc::AF_INET, libc::IPPROTO_ICMP)
+        };
+        let mut last_error = None;
+        for (kind, raw) in [(libc::SOCK_DGRAM, false), (libc::SOCK_RAW, true)] {
+            let fd = unsafe { libc::socket(domain, kind | libc::SOCK_CLOEXEC, protocol) };
+            if fd >= 0 {
+                return Ok((unsafe { UdpSocket::from_raw_fd(fd) }, raw));
+            }
+            last_error = Some(io::Error::last_os_error());
+        }
+        Err(last_error.expect("tried at least one socket type"))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod icmp {
+    use std::io;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    use crate::connector::Connector;
+
+    pub async fn echo(_ip: IpAddr, _timeout: Duration, _connector: &Connector) -> io::Result<bool> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "ICMP echo is only supported on Linux"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_refused_connection_means_up() {
+        let closed = {
+            let l = TcpListener::bind("127.0.0.1:0").unwrap();
+            l.local_addr().unwrap()
+        };
+        let options = DiscoveryOptions { tcp_ports: vec![closed.port()], udp_ports: vec![], icmp: false };
+        let status = discover_host("localhost", closed.ip(), &options, &Timing::default(), &Connector::default()).await;
+        assert!(status.up);
+        assert_eq!(status.reason, format!("conn-refused (tcp/{})", closed.port()));
+        assert!(status.latency_ms.is_some());
+
+        let options = DiscoveryOptions { tcp_ports: vec![], udp_ports: vec![], icmp: false };
+        let status = discover_host("localhost", closed.ip(), &options, &Timing::default(), &Connector::default()).await;
+        assert!(!status.up);
+        assert_eq!((status.reason.as_str(), status.latency_ms), ("no-response", None));
+    }
+
+    #[tokio::test]
+    async fn test_icmp_echo_loopback() {
+        // Skip where neither ping nor raw sockets are allowed
+        let Ok(answered) = icmp::echo("127.0.0.1".parse().unwrap(), Duration::from_secs(1), &Connector::default()).await else { return };
+        assert!(answered);
+    }
+}
@@ -1,16 +1,30 @@
+use std::net::SocketAddr;
+
+use crate::connector::Connector;
 use crate::service_info::{ServiceInfo, Protocol, PortState};
 use crate::signatures::SignatureMatcher;
-use crate::protocols::{http, tls, ssh, smtp_ftp};
+use crate::protocols::{http, tls, ssh, smtp_ftp, udp_probes};
+use crate::timing::Timing;
 
 /// Main fingerprinting orchestrator
-/// Takes an open port and attempts to identify the service running on it
+/// Takes an open port and attempts to identify the service running on it.
+/// `host` is the target as the user gave it (used for Host headers and SNI),
+/// `addr` the resolved address every probe connects to.
+/// For UDP, `udp_reply` is the datagram the port answered with.
+/// TCP probes use `timing`'s probe timeouts and stop once its service budget is spent,
+/// and open their connections through `connector`.
 pub fn fingerprint_service(
-    addr: &str,
-    port: u16,
+    host: &str,
+    addr: SocketAddr,
     protocol: Protocol,
+    udp_reply: Option<&[u8]>,
     matcher: &SignatureMatcher,
+    timing: &Timing,
+    connector: &Connector,
 ) -> ServiceInfo {
-    let mut info = ServiceInfo::new(port, protocol.clone(), PortState::Open);
+    let port = addr.port();
+    let mut info = ServiceInfo::new(host.to_string(), port, protocol.clone(), PortState::Open)
+        .with_ip(addr.ip());
 
     // Start with port-based hint
     if let Some(hint) = matcher.get_port_hint(port) {
@@ -18,37 +32,62 @@ pub fn fingerprint_service(
     }
 
     match protocol {
-        Protocol::TCP => fingerprint_tcp(addr, port, matcher, info),
-        Protocol::UDP => {
-            // UDP fingerprinting is limited
-            info.state = PortState::Filtered;
-            info
+        Protocol::TCP => fingerprint_tcp(host, addr, matcher, timing, connector, info),
+        Protocol::UDP => fingerprint_udp(port, udp_reply.unwrap_or_default(), info),
+    }
+}
+
+/// Fingerprint UDP services from the reply to the port's protocol payload
+fn fingerprint_udp(port: u16, reply: &[u8], mut info: ServiceInfo) -> ServiceInfo {
+    match udp_probes::identify_reply(port, reply) {
+        Some(identified) => {
+            info = info.with_service(identified.service, identified.confidence);
+            if let Some(ver) = identified.version {
+                info = info.with_version(ver);
+            }
+            if let Some(banner) = identified.banner {
+                info = info.with_banner(banner);
+            }
+        }
+        None if info.service.is_none() => {
+            // The port answered, so it is open, but the reply was not recognised
+            info = info.with_service("unknown".to_string(), 0.1);
         }
+        None => {}
     }
+
+    info
 }
 
-/// Fingerprint TCP services
+/// Fingerprint TCP services, skipping the remaining probes once the service budget is spent
 fn fingerprint_tcp(
-    addr: &str,
-    port: u16,
+    host: &str,
+    addr: SocketAddr,
     matcher: &SignatureMatcher,
+    timing: &Timing,
+    connector: &Connector,
     mut info: ServiceInfo,
 ) -> ServiceInfo {
+    let port = addr.port();
+    let deadline = timing.service_deadline();
+    let next_probe = || timing.within(deadline);
+
     // Try SSH first (common and quick)
     if ssh::is_likely_ssh_port(port) || info.service.as_deref() == Some("ssh") {
-        if let Some(ssh_banner) = ssh::probe_ssh(addr, port) {
+        if let Some(ssh_banner) = next_probe().and_then(|t| ssh::probe_ssh(addr, &t, connector)) {
             let full_banner = format!("{} {}", ssh_banner.software,
                 ssh_banner.comments.as_deref().unwrap_or(""));
 
             // Try to match against signatures
             if let Some(matched) = matcher.match_banner(&full_banner) {
-                info = info.with_service(matched.product.clone(), matched.confidence);
+                info = info.with_service(matched.product.clone(), matched.confidence).with_product(matched.product);
                 if let Some(ver) = matched.version {
                     info = info.with_version(ver);
                 }
             } else {
                 info = info.with_service(ssh_banner.software.clone(), 0.8);
             }
+            info = info.with_product(ssh_banner.software);
 
             info = info.with_banner(full_banner);
             return info;
@@ -57,11 +96,11 @@ fn fingerprint_tcp(
 
     // Try FTP
     if smtp_ftp::is_likely_ftp_port(port) || info.service.as_deref() == Some("ftp") {
-        if let Some(greeting) = smtp_ftp::probe_ftp(addr, port) {
+        if let Some(greeting) = next_probe().and_then(|t| smtp_ftp::probe_ftp(addr, &t, connector)) {
             info = info.with_banner(greeting.clone());
 
             if let Some(matched) = matcher.match_banner(&greeting) {
-                info = info.with_service(matched.product.clone(), matched.confidence);
+                info = info.with_service(matched.product.clone(), matched.confidence).with_product(matched.product);
                 if let Some(ver) = matched.version {
                     info = info.with_version(ver);
                 }
@@ -74,11 +113,11 @@ fn fingerprint_tcp(
 
     // Try SMTP
     if smtp_ftp::is_likely_smtp_port(port) || info.service.as_deref() == Some("smtp") {
-        if let Some(greeting) = smtp_ftp::probe_smtp(addr, port) {
+        if let Some(greeting) = next_probe().and_then(|t| smtp_ftp::probe_smtp(addr, &t, connector)) {
             info = info.with_banner(greeting.clone());
 
             if let Some(matched) = matcher.match_banner(&greeting) {
-                info = info.with_service(matched.product.clone(), matched.confidence);
+                info = info.with_service(matched.product.clone(), matched.confidence).with_product(matched.product);
                 if let Some(ver) = matched.version {
                     info = info.with_version(ver);
                 }
@@ -91,11 +130,11 @@ fn fingerprint_tcp(
 
     // Try POP3
     if smtp_ftp::is_likely_pop3_port(port) || info.service.as_deref() == Some("pop3") {
-        if let Some(greeting) = smtp_ftp::probe_pop3(addr, port) {
+        if let Some(greeting) = next_probe().and_then(|t| smtp_ftp::probe_pop3(addr, &t, connector)) {
             info = info.with_banner(greeting.clone());
 
             if let Some(matched) = matcher.match_banner(&greeting) {
-                info = info.with_service(matched.product.clone(), matched.confidence);
+                info = info.with_service(matched.product.clone(), matched.confidence).with_product(matched.product);
                 if let Some(ver) = matched.version {
                     info = info.with_version(ver);
                 }
@@ -108,11 +147,11 @@ fn fingerprint_tcp(
 
     // Try IMAP
     if smtp_ftp::is_likely_imap_port(port) || info.service.as_deref() == Some("imap") {
-        if let Some(greeting) = smtp_ftp::probe_imap(addr, port) {
+        if let Some(greeting) = next_probe().and_then(|t| smtp_ftp::probe_imap(addr, &t, connector)) {
             info = info.with_banner(greeting.clone());
 
             if let Some(matched) = matcher.match_banner(&greeting) {
-                info = info.with_service(matched.product.clone(), matched.confidence);
+                info = info.with_service(matched.product.clone(), matched.confidence).with_product(matched.product);
                 if let Some(ver) = matched.version {
                     info = info.with_version(ver);
                 }
@@ -125,7 +164,7 @@ fn fingerprint_tcp(
 
     // Try TLS for HTTPS and other TLS services
     if tls::is_likely_tls_port(port) || info.service.as_deref() == Some("https") {
-        if let Some(tls_info) = tls::probe_tls(addr, port) {
+        if let Some(tls_info) = next_probe().and_then(|t| tls::probe_tls(addr, host, &t, connector)) {
             info = info.with_tls_info(tls_info);
 
             // If we got TLS info, it's likely HTTPS
@@ -143,7 +182,7 @@ fn fingerprint_tcp(
 
     // Try HTTP (should be tried after TLS for HTTPS ports)
     if port == 80 || port == 8080 || port == 8000 || info.service.as_deref() == Some("http") {
-        if let Some(http_response) = http::probe_http(addr, port, false) {
+        if let Some(http_response) = next_probe().and_then(|t| http::probe_http(addr, host, false, &t, connector)) {
             if let Some(server) = http::extract_server_info(&http_response) {
                 if let Some(matched) = matcher.match_http_server(&server) {
                     info = info.with_service(matched.product.clone(), matched.confidence);
@@ -153,6 +192,7 @@ fn fingerprint_tcp(
                 } else {
                     info = info.with_service("HTTP".to_string(), 0.7);
                 }
+                info = info.with_product(server);
 
                 info = info.with_banner(http_response.status_line.clone());
             } else {
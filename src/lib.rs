@@ -0,0 +1,59 @@
+//! Concurrent TCP/UDP port scanning and service fingerprinting.
+//!
+//! Configure a scan with [`Scanner::builder`], start it on a tokio runtime and
+//! read results as they arrive:
+//!
+//! ```no_run
+//! use port_scanner::{Scanner, TimingTemplate};
+//!
+//! # async fn example() -> Result<(), String> {
+//! let scanner = Scanner::builder()
+//!     .targets("10.0.0.0/30,example.com")
+//!     .port_spec("22,80,443,U:53")
+//!     .timing_template(TimingTemplate::Polite)
+//!     .build()?;
+//!
+//! let mut scan = scanner.start();
+//! while let Some(info) = scan.next().await {
+//!     println!("{}", info.display_full());
+//! }
+//! scan.finish().await
+//! # }
+//! ```
+//!
+//! The lower-level pieces (the scan engine in [`scanner`], [`fingerprint::fingerprint_service`]
+//! and the protocol probes in [`protocols`]) are public as well.
+
+// mod banner;
+pub mod checkpoint;
+pub mod connector;
+pub mod discovery;
+pub mod fingerprint;
+pub mod order;
+pub mod ports;
+pub mod protocols;
+pub mod proxy;
+mod rate;
+pub mod report;
+pub mod rtt;
+pub mod scan;
+pub mod scanner;
+pub mod service_info;
+pub mod signatures;
+pub mod sink;
+pub mod syn;
+pub mod targets;
+pub mod timing;
+pub mod udp;
+mod util;
+
+pub use connector::Connector;
+pub use discovery::{DiscoveryOptions, HostStatus};
+pub use order::ScanOrder;
+pub use proxy::Proxy;
+pub use scan::{Scan, Scanner, ScannerBuilder};
+pub use service_info::{PortState, Protocol, ServiceInfo, TlsInfo};
+pub use signatures::SignatureMatcher;
+pub use sink::{ResultSink, ScanEvent};
+pub use targets::IpFamily;
+pub use timing::{Timing, TimingOverrides, TimingTemplate};
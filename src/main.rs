@@ -1,138 +1,556 @@
-// mod banner;
-mod scanner;
-mod udp;
-mod util;
 mod progress;
-mod service_info;
-mod signatures;
-mod fingerprint;
-mod protocols;
+mod summary;
 mod web_state;
 mod web_server;
 
-use std::sync::{Arc, Mutex, mpsc};
-use std::sync::atomic::AtomicUsize;
-use std::thread;
-use clap::Parser;
-use service_info::ServiceInfo;
-use signatures::SignatureMatcher;
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use clap::{Args, Parser, Subcommand};
+use port_scanner::ports::{self, PortSpec};
+use port_scanner::report::{self, ScanReport};
+use port_scanner::scan::DEFAULT_SIGNATURES;
+use port_scanner::signatures::SignatureDatabase;
+use port_scanner::sink::{JsonLinesSink, PrettySink};
+use port_scanner::targets::{self, IpFamily, TargetEntry};
+use port_scanner::{DiscoveryOptions, Proxy, ScanEvent, Scanner, SignatureMatcher, TimingOverrides, TimingTemplate};
+use tokio_util::sync::CancellationToken;
 
 #[derive(Parser, Debug)]
-#[command(name = "port-scanner", about = "A fast, concurrent TCP/UDP port scanner")]
-struct Opts {
-    #[arg(short = 'd', long, default_value = "127.0.0.1")]
-    target: String,
+#[command(
+    name = "port-scanner",
+    about = "A fast, concurrent TCP/UDP port scanner",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    /// Signature database used for fingerprinting and --top-ports
+    #[arg(long, global = true, value_name = "FILE", default_value = DEFAULT_SIGNATURES)]
+    signature_file: String,
 
-    #[arg(short = 's', long, default_value_t = 1)]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Deprecated spelling of `serve`, kept so existing scripts still start the web UI
+    #[arg(short = 'w', long, hide = true)]
+    web: bool,
+
+    /// Without a subcommand the arguments are those of `scan`
+    #[command(flatten)]
+    scan: ScanArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Scan hosts for open ports and fingerprint their services (the default)
+    Scan(Box<ScanArgs>),
+    /// Run the web UI
+    Serve(ServeArgs),
+    /// Check, list or try out the signature database
+    #[command(subcommand)]
+    Signatures(SignaturesCommand),
+    /// Print the summary of results saved with `scan --output` or a checkpoint file
+    Report {
+        file: PathBuf,
+    },
+    /// Show the open ports that appeared, disappeared or changed service between two saved scans
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+    },
+}
+
+#[derive(Args, Debug)]
+struct ServeArgs {
+    /// Address the web UI listens on
+    #[arg(long, default_value = "127.0.0.1")]
+    bind: IpAddr,
+
+    #[arg(short = 'p', long, default_value_t = 9876)]
+    port: u16,
+}
+
+#[derive(Subcommand, Debug)]
+enum SignaturesCommand {
+    /// Report patterns that do not compile, bad confidences and invalid port hints
+    Validate,
+    /// List every banner and HTTP Server pattern
+    List,
+    /// Show which signature matches a service banner or HTTP Server header
+    Test {
+        text: String,
+
+        /// Match TEXT against the HTTP Server header patterns instead of the banner patterns
+        #[arg(long)]
+        http: bool,
+    },
+}
+
+#[derive(Args, Debug)]
+struct ScanArgs {
+    /// Hosts to scan: hostnames, IPs, CIDR blocks (10.0.0.0/24),
+    /// ranges (10.0.0.1-50) or a comma-separated mix of them [default: 127.0.0.1]
+    #[arg(short = 'd', long)]
+    target: Option<String>,
+
+    /// Which resolved addresses to scan; `both` scans IPv4 and IPv6
+    /// addresses of dual-stack hosts and reports them separately
+    #[arg(long, value_enum, default_value_t = IpFamily::Auto)]
+    ip_family: IpFamily,
+
+    /// Read targets from a file (one host, CIDR, range or host:port per line; `-` for stdin)
+    #[arg(short = 'i', long)]
+    target_file: Option<String>,
+
+    /// Ports to scan, e.g. `22,80,443,8000-8100`, `T:80,U:53,161` or `-` for all,
+    /// instead of using --start-port/--end-port
+    #[arg(short = 'p', long, conflicts_with_all = ["port_file", "top_ports"])]
+    ports: Option<String>,
+
+    /// Read ports from a file (one port specification per line; `-` for stdin)
+    #[arg(long, conflicts_with = "top_ports")]
+    port_file: Option<String>,
+
+    /// Scan the N most common TCP and UDP ports from signatures.json
+    /// (its tables rank 100 TCP and 50 UDP ports)
+    #[arg(long, value_name = "N")]
+    top_ports: Option<usize>,
+
+    #[arg(short = 's', long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
     start_port: u16,
 
-    #[arg(short = 'e', long, default_value_t = 1024)]
+    #[arg(short = 'e', long, default_value_t = 1024, value_parser = clap::value_parser!(u16).range(1..))]
     end_port: u16,
 
-    #[arg(short = 't', long, default_value_t = 10)]
-    threads: usize,
+    /// Timing template setting timeouts, retries, concurrency and rate together;
+    /// the individual flags below override single values of it
+    #[arg(short = 'T', long, value_enum, default_value_t = TimingTemplate::Normal)]
+    timing: TimingTemplate,
 
-    #[arg(short = 'c', long, default_value_t = 50)]
-    timeout_ms: u64,
+    /// Maximum number of ports probed at the same time [normal: 500]
+    #[arg(short = 't', long, visible_alias = "threads")]
+    concurrency: Option<usize>,
 
-    #[arg(short = 'u', long, default_value_t = 100)]
-    udp_timeout_ms: u64,
+    /// Send at most this many port probes and discovery pings per second across the whole scan;
+    /// 0 lifts the timing template's limit. The connections that fingerprint
+    /// open ports are not paced.
+    #[arg(long, value_name = "PROBES_PER_SEC")]
+    max_rate: Option<u32>,
 
-    /// Launch web UI instead of CLI mode
-    #[arg(short = 'w', long)]
-    web: bool,
+    /// Probe at most this many ports of the same host at the same time
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_per_host: Option<u64>,
+
+    /// Probe the (host, port) pairs in a random order instead of host by host
+    /// with ascending ports
+    #[arg(long)]
+    randomize: bool,
+
+    /// Seed for --randomize, to repeat the order of an earlier scan (implies --randomize)
+    #[arg(long, value_name = "N")]
+    seed: Option<u64>,
+
+    /// Take hosts in turn, one port each, so the load is spread across targets
+    #[arg(long)]
+    interleave: bool,
+
+    /// Resend probes that timed out up to this many times, with a doubling backoff [normal: 1]
+    #[arg(long)]
+    retries: Option<u32>,
+
+    /// Initial TCP connect timeout; once a host answers, its timeout adapts to the measured RTT.
+    /// Also the fingerprinting connect timeout unless --probe-timeout-ms is given [normal: 1000]
+    #[arg(short = 'c', long)]
+    timeout_ms: Option<u64>,
+
+    /// Lower bound for adaptive TCP connect timeouts [normal: 50]
+    #[arg(long)]
+    min_timeout_ms: Option<u64>,
+
+    /// Upper bound for adaptive TCP connect timeouts [normal: 3000]
+    #[arg(long)]
+    max_timeout_ms: Option<u64>,
+
+    /// How long to wait for a UDP reply [normal: 100]
+    #[arg(short = 'u', long)]
+    udp_timeout_ms: Option<u64>,
+
+    /// Connect and read timeout of each service fingerprinting probe [normal: 500 connect, 1000 read]
+    #[arg(long)]
+    probe_timeout_ms: Option<u64>,
+
+    /// Scan TCP ports (only TCP unless --udp is also given)
+    #[arg(long)]
+    tcp: bool,
+
+    /// Scan UDP ports (only UDP unless --tcp is also given)
+    #[arg(long)]
+    udp: bool,
+
+    /// Also report closed and filtered ports (useful for verifying firewall rules)
+    #[arg(long)]
+    show_closed: bool,
+
+    /// Half-open SYN scan over raw sockets instead of full TCP connects
+    /// (Linux only, needs root or CAP_NET_RAW)
+    #[arg(long)]
+    syn: bool,
+
+    /// Send every probe from this local address, e.g. to test firewall rules per VLAN
+    #[arg(long, value_name = "IP")]
+    source_ip: Option<IpAddr>,
+
+    /// Bind every probe to this network device (Linux only), e.g. `eth0.20`
+    #[arg(long, value_name = "DEVICE")]
+    interface: Option<String>,
+
+    /// Tunnel every TCP connection through a proxy: `socks5://[user:pass@]host[:port]`
+    /// or `http://[user:pass@]host[:port]` (HTTP CONNECT); scans TCP only, and
+    /// targets are resolved locally
+    #[arg(long, value_name = "URL", conflicts_with = "syn")]
+    proxy: Option<Proxy>,
+
+    /// Scan every host without first checking that it is up
+    #[arg(long)]
+    skip_discovery: bool,
+
+    /// Ports pinged during host discovery; a host answering on any of them,
+    /// even with a RST or ICMP port-unreachable, is up
+    #[arg(long, value_name = "PORTS", default_value = "T:22,80,443,U:53", conflicts_with = "skip_discovery")]
+    ping_ports: String,
+
+    /// Do not send ICMP echo requests during host discovery
+    /// (they are only sent where ping or raw sockets are permitted)
+    #[arg(long, conflicts_with = "skip_discovery")]
+    no_icmp_ping: bool,
+
+    /// Periodically save progress to this file so an interrupted scan can be resumed
+    #[arg(long, value_name = "FILE")]
+    checkpoint: Option<PathBuf>,
+
+    /// Continue the scan saved in this checkpoint file, with the targets, ports and
+    /// options it was started with; the file keeps being updated as the scan goes on.
+    /// Only the output flags (--checkpoint, --jsonl, --output) can be given with it, and
+    /// --proxy to pass the proxy's credentials again, since checkpoints do not keep them.
+    #[arg(long, value_name = "FILE", conflicts_with_all = [
+        "target", "ip_family", "target_file", "ports", "port_file", "top_ports", "start_port", "end_port",
+        "timing", "concurrency", "max_rate", "max_per_host", "randomize", "seed", "interleave", "retries",
+        "timeout_ms", "min_timeout_ms", "max_timeout_ms", "udp_timeout_ms", "probe_timeout_ms",
+        "tcp", "udp", "show_closed", "syn", "source_ip", "interface",
+        "skip_discovery", "ping_ports", "no_icmp_ping",
+    ])]
+    resume: Option<PathBuf>,
+
+    /// Also write every scan event (open ports, results, finished hosts) to this file as JSON lines
+    #[arg(long, value_name = "FILE")]
+    jsonl: Option<PathBuf>,
+
+    /// Save the results to this file for `report` and `diff`
+    #[arg(short = 'o', long, value_name = "FILE")]
+    output: Option<PathBuf>,
 }
 
 #[tokio::main]
 async fn main() {
-    let opts = Opts::parse();
-
-    // Load signature database
-    let matcher = match SignatureMatcher::load("signatures.json") {
-        Ok(m) => Arc::new(m),
-        Err(e) => {
-            eprintln!("Warning: Failed to load signatures.json: {}", e);
-            eprintln!("Continuing with basic port scanning only...");
-            return;
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        None if cli.web => {
+            eprintln!("Warning: --web is deprecated; use `port-scanner serve` instead");
+            let args = ServeArgs { bind: Ipv4Addr::LOCALHOST.into(), port: 9876 };
+            serve(args, &cli.signature_file).await
         }
+        None => scan(cli.scan, &cli.signature_file).await,
+        Some(Command::Scan(args)) => scan(*args, &cli.signature_file).await,
+        Some(Command::Serve(args)) => serve(args, &cli.signature_file).await,
+        Some(Command::Signatures(command)) => signatures(command, &cli.signature_file),
+        Some(Command::Report { file }) => ScanReport::load(&file).map(|report| summary::print_summary(&report, None)),
+        Some(Command::Diff { old, new }) => diff(&old, &new),
     };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
 
-    // Check if web mode is requested
-    if opts.web {
-        if let Err(e) = web_server::run_web_server(matcher).await {
-            eprintln!("Web server error: {}", e);
+fn diff(old_path: &Path, new_path: &Path) -> Result<(), String> {
+    let (old, new) = (ScanReport::load(old_path)?, ScanReport::load(new_path)?);
+    for (path, report) in [(old_path, &old), (new_path, &new)] {
+        if report.cancelled {
+            eprintln!("Warning: {} is from a cancelled scan; ports it never reached may show as changed", path.display());
         }
-        return;
     }
+    summary::print_diff(&report::diff(&old, &new));
+    Ok(())
+}
 
-    // CLI mode
-    println!("Starting scan on target: {}", opts.target);
-
-    let target = Arc::new(opts.target);
-    let start_port = opts.start_port;
-    let end_port = opts.end_port;
-    let total_ports = (end_port - start_port + 1) as usize;
-    let timeout_ms = opts.timeout_ms;
-    let udp_timeout_ms = opts.udp_timeout_ms;
-
-    let completed = Arc::new(AtomicUsize::new(0));
-    let reporter_handle = progress::spawn_reporter(total_ports, Arc::clone(&completed));
-
-    let (task_tx, task_rx_raw) = mpsc::channel::<u16>();
-    let (res_tx,  res_rx) = mpsc::channel::<ServiceInfo>();
-
-    // Wrap the receiver so it can be shared by multiple workers
-    let task_rx = Arc::new(Mutex::new(task_rx_raw));
-
-    // Create worker threads
-    let mut handles = Vec::with_capacity(10);
-    for _ in 0..opts.threads {
-        let task_rx   = Arc::clone(&task_rx);
-        let res_tx = res_tx.clone();
-        let target = Arc::clone(&target);
-        let completed = Arc::clone(&completed);
-        let matcher = Arc::clone(&matcher);
-
-        let handle = thread::spawn(move || {
-            // Delegate to scanner module
-            scanner::worker_loop(
-                task_rx,
-                res_tx,
-                target,
-                completed,
-                timeout_ms,
-                udp_timeout_ms,
-                matcher
+fn load_matcher(path: &str) -> Result<Arc<SignatureMatcher>, String> {
+    SignatureMatcher::load(path)
+        .map(Arc::new)
+        .map_err(|e| format!("Failed to load {}: {}", path, e))
+}
+
+async fn serve(args: ServeArgs, signature_file: &str) -> Result<(), String> {
+    let matcher = load_matcher(signature_file)?;
+    web_server::run_web_server(matcher, SocketAddr::new(args.bind, args.port))
+        .await
+        .map_err(|e| format!("Web server error: {}", e))
+}
+
+fn signatures(command: SignaturesCommand, signature_file: &str) -> Result<(), String> {
+    match command {
+        SignaturesCommand::Validate => {
+            let database = SignatureDatabase::load(signature_file)
+                .map_err(|e| format!("Failed to load {}: {}", signature_file, e))?;
+            let problems = database.problems();
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if !problems.is_empty() {
+                return Err(format!("{}: {} problem(s) found", signature_file, problems.len()));
+            }
+            println!(
+                "{}: {} banner and {} HTTP Server patterns, {} port hints, all valid",
+                signature_file,
+                database.banner_patterns.len(),
+                database.http_server_patterns.len(),
+                database.port_hints.len()
             );
-        });
-        handles.push(handle);
+        }
+        SignaturesCommand::List => {
+            let matcher = load_matcher(signature_file)?;
+            let database = matcher.database();
+            println!("Banner patterns:");
+            for p in &database.banner_patterns {
+                println!("  {:<8} {:<24} {:>4.0}%  {}", p.service, p.product, p.confidence * 100.0, p.pattern);
+            }
+            println!("HTTP Server patterns:");
+            for p in &database.http_server_patterns {
+                println!("  {:<8} {:<24} {:>4.0}%  {}", p.service, p.product, p.confidence * 100.0, p.pattern);
+            }
+        }
+        SignaturesCommand::Test { text, http } => {
+            let matcher = load_matcher(signature_file)?;
+            let found = if http { matcher.match_http_server(&text) } else { matcher.match_banner(&text) };
+            let Some(m) = found else {
+                return Err("No signature matches".to_string());
+            };
+            let version = m.version.map(|v| format!(" v{}", v)).unwrap_or_default();
+            println!("{}: {}{} [confidence: {:.0}%]", m.service, m.product, version, m.confidence * 100.0);
+        }
+    }
+    Ok(())
+}
+
+async fn scan(opts: ScanArgs, signature_file: &str) -> Result<(), String> {
+    let matcher = load_matcher(signature_file)?;
+
+    // Start a new scan, or continue one from its checkpoint
+    let scanner = match opts.resume {
+        Some(ref path) => Scanner::resume(path.clone(), Arc::clone(&matcher), opts.proxy.clone()),
+        None => build_scanner(&opts, Arc::clone(&matcher)),
+    };
+    let mut scanner = scanner?;
+    for error in scanner.dns_errors() {
+        eprintln!("{}", error);
+    }
+
+    // A resumed scan keeps updating the checkpoint it was resumed from
+    // unless --checkpoint names another file
+    let checkpoint_path = opts.checkpoint.clone().or_else(|| opts.resume.clone());
+    if let Some(ref path) = opts.checkpoint {
+        scanner = scanner.with_checkpoint(path.clone());
     }
-    drop(res_tx);
 
-    for port in start_port..=end_port {
-        let _ = task_tx.send(port);
+    let hosts = scanner.hosts();
+    let addresses = scanner.addresses();
+    let (targets, ports) = (scanner.targets().to_vec(), scanner.ports().cloned());
+    let show_closed = scanner.options().show_closed;
+    let total_probes = scanner.total_probes();
+    if opts.resume.is_some() {
+        println!(
+            "Resuming scan on {} host(s): {} of {} probes done",
+            hosts.len(),
+            scanner.completed_probes(),
+            total_probes
+        );
+    } else {
+        println!("Starting scan on {} host(s)", hosts.len());
+        if let Some(seed) = scanner.options().order.seed {
+            println!("Probing in random order, seed {} (repeat with --seed {})", seed, seed);
+        }
     }
-    drop(task_tx);
+    let mut results = scanner.resumed_results().to_vec();
 
-    let mut results: Vec<ServiceInfo> = Vec::new();
-    for service_info in res_rx {
-        results.push(service_info);
+    // Open ports are printed as they are identified; the summary lists everything at the end
+    scanner = scanner.with_sink(Arc::new(PrettySink::stdout()));
+    if let Some(ref path) = opts.jsonl {
+        let sink = JsonLinesSink::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        scanner = scanner.with_sink(Arc::new(sink));
     }
 
-    // Wait for all worker threads to finish
-    for handle in handles {
-        let _ = handle.join();
+    let mut scan = scanner.start();
+    tokio::spawn(cancel_on_ctrl_c(scan.cancel_token()));
+    let reporter_handle = progress::spawn_reporter(total_probes, scan.completed(), scan.cancel_token());
+
+    let mut discovery = Vec::new();
+    while let Some(event) = scan.next_event().await {
+        match event {
+            ScanEvent::Service(service_info) => results.push(service_info),
+            ScanEvent::Discovery(status) => {
+                if !status.up {
+                    println!(
+                        "[DOWN] {} ({}): {} - not scanned (use --skip-discovery to scan it anyway)",
+                        status.host, status.ip, status.reason
+                    );
+                }
+                discovery.push(status);
+            }
+            _ => {}
+        }
     }
+
+    // Wait for the scan engine and the progress reporter to finish
+    let cancelled = scan.is_cancelled();
+    let (completed, _) = scan.progress();
+    let rtt = scan.rtt();
+    let finished = scan.finish().await;
     let _ = reporter_handle.join();
 
-    // Print summary
-    println!("\n========== SCAN SUMMARY ==========");
-    println!("Total open ports found: {}", results.len());
-    println!("==================================\n");
+    if let Some(path) = checkpoint_path {
+        match finished {
+            Ok(()) if cancelled => println!("Checkpoint saved; continue with --resume {}", path.display()),
+            Ok(()) => {}
+            Err(e) => eprintln!("Warning: failed to write checkpoint: {}", e),
+        }
+    }
+
+    let report = ScanReport {
+        addresses,
+        targets,
+        ports,
+        show_closed,
+        total_probes,
+        completed_probes: completed,
+        cancelled,
+        results,
+        discovery,
+    };
+    summary::print_summary(&report, Some(&rtt));
+
+    if cancelled {
+        println!("\nScan cancelled.");
+    } else {
+        println!("\nScan complete.");
+    }
+
+    match opts.output {
+        Some(ref path) => report.save(path),
+        None => Ok(()),
+    }
+}
+
+/// Cancels the scan on the first Ctrl-C so in-flight probes drain and the partial
+/// results are still summarised; a second Ctrl-C exits immediately
+async fn cancel_on_ctrl_c(cancel: CancellationToken) {
+    if tokio::signal::ctrl_c().await.is_err() {
+        return;
+    }
+    eprintln!("\nCancelling scan, waiting for in-flight probes (press Ctrl-C again to quit)...");
+    cancel.cancel();
+    if tokio::signal::ctrl_c().await.is_ok() {
+        std::process::exit(130);
+    }
+}
+
+/// Configures a fresh scan from the targets, ports and timing given on the command line
+fn build_scanner(opts: &ScanArgs, matcher: Arc<SignatureMatcher>) -> Result<Scanner, String> {
+    let target_list = load_targets(opts).map_err(|e| format!("Invalid target: {}", e))?;
+    let ports = load_ports(opts, &matcher).map_err(|e| format!("Invalid port list: {}", e))?;
+    let ping_ports = ports::parse_port_spec(&opts.ping_ports).map_err(|e| format!("Invalid --ping-ports: {}", e))?;
+    let discovery = DiscoveryOptions { tcp_ports: ping_ports.tcp, udp_ports: ping_ports.udp, icmp: !opts.no_icmp_ping };
+
+    let overrides = TimingOverrides {
+        timeout_ms: opts.timeout_ms,
+        min_timeout_ms: opts.min_timeout_ms,
+        max_timeout_ms: opts.max_timeout_ms,
+        udp_timeout_ms: opts.udp_timeout_ms,
+        probe_timeout_ms: opts.probe_timeout_ms,
+        retries: opts.retries,
+        concurrency: opts.concurrency,
+        max_rate: opts.max_rate.map(|rate| (rate > 0).then_some(rate)),
+    };
+    Scanner::builder()
+        .target_entries(target_list)
+        .ip_family(opts.ip_family)
+        .ports(ports)
+        .protocols(opts.tcp, opts.udp)
+        .timing(opts.timing.timing().with_overrides(&overrides))
+        .max_per_host(opts.max_per_host.map(|n| n as usize))
+        .show_closed(opts.show_closed)
+        .randomize(opts.randomize)
+        .seed(opts.seed)
+        .interleave(opts.interleave)
+        .syn_scan(opts.syn)
+        .discovery(discovery)
+        .skip_discovery(opts.skip_discovery)
+        .source_ip(opts.source_ip)
+        .interface(opts.interface.clone())
+        .proxy(opts.proxy.clone())
+        .signatures(matcher)
+        .build()
+}
+
+/// Picks the ports to scan from --ports, --port-file or --top-ports,
+/// falling back to the --start-port/--end-port range
+fn load_ports(opts: &ScanArgs, matcher: &SignatureMatcher) -> Result<PortSpec, String> {
+    if let Some(ref spec) = opts.ports {
+        return ports::parse_port_spec(spec);
+    }
+    if let Some(ref path) = opts.port_file {
+        return ports::read_port_file(path);
+    }
+    if let Some(n) = opts.top_ports {
+        let ports = matcher.top_ports(n);
+        if ports.is_empty() {
+            return Err("signatures.json has no top port table".to_string());
+        }
+        // The tables are shorter than a full frequency list, so say when they run out
+        let (tcp, udp) = (opts.tcp || !opts.udp, opts.udp || !opts.tcp);
+        for (protocol, selected, ranked) in [("TCP", tcp, ports.tcp.len()), ("UDP", udp, ports.udp.len())] {
+            if selected && ranked < n {
+                eprintln!("Warning: the signature database only ranks {} {} ports; scanning those instead of {}", ranked, protocol, n);
+            }
+        }
+        return Ok(ports);
+    }
+    if opts.end_port < opts.start_port {
+        return Err(format!("--end-port {} is below --start-port {}", opts.end_port, opts.start_port));
+    }
+    Ok(PortSpec::range(opts.start_port, opts.end_port))
+}
+
+/// Collects targets from `--target` and `--target-file`,
+/// falling back to localhost when neither is given
+fn load_targets(opts: &ScanArgs) -> Result<Vec<TargetEntry>, String> {
+    if opts.target_file.as_deref() == Some("-") && opts.port_file.as_deref() == Some("-") {
+        return Err("Only one of --target-file and --port-file can read from stdin".to_string());
+    }
 
-    for info in &results {
-        println!("[RESULT] {}", info.display_full());
+    let mut entries = Vec::new();
+    if let Some(ref spec) = opts.target {
+        entries.extend(targets::parse_targets(spec)?.into_iter().map(TargetEntry::host));
     }
+    if let Some(ref path) = opts.target_file {
+        entries.extend(targets::read_target_file(path)?);
+    }
+    if entries.is_empty() {
+        entries.push(TargetEntry::host("127.0.0.1".to_string()));
+    }
+
+    // The same target may appear both on the command line and in the file
+    let mut seen = HashSet::new();
+    entries.retain(|entry| seen.insert(entry.clone()));
 
-    println!("\nScan complete.");
+    Ok(entries)
 }
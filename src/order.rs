@@ -0,0 +1,153 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::scanner::WorkItem;
+
+/// The order work items are probed in. The default keeps `build_work_items`'
+/// order: target by target, ports ascending.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanOrder {
+    /// Shuffles every (host, port) pair with this seed; the same seed, targets
+    /// and ports always give the same order
+    pub seed: Option<u64>,
+    /// Takes hosts in turn, one work item each, so no host sees a run of
+    /// consecutive probes while others wait
+    pub interleave: bool,
+}
+
+impl ScanOrder {
+    /// Reorders the work items: shuffled first if a seed is set, then interleaved
+    /// by address. Interleaving a shuffled scan keeps each host's shuffled port
+    /// order and takes hosts in the order their first item was drawn.
+    pub fn apply(&self, mut items: Vec<WorkItem>) -> Vec<WorkItem> {
+        if let Some(seed) = self.seed {
+            shuffle(&mut items, seed);
+        }
+        if self.interleave {
+            items = interleave(items);
+        }
+        items
+    }
+}
+
+/// A seed for scans that are randomized without one, differing between runs
+pub fn random_seed() -> u64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(nanos);
+    hasher.finish()
+}
+
+/// Fisher-Yates shuffle driven by SplitMix64, which is small, fast and fully
+/// determined by its seed on every platform
+fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut rng = SplitMix64(seed);
+    for i in (1..items.len()).rev() {
+        items.swap(i, rng.below(i + 1));
+    }
+}
+
+/// Round-robin over the addresses, keeping each address's items in order
+fn interleave(items: Vec<WorkItem>) -> Vec<WorkItem> {
+    let total = items.len();
+    let mut queues: Vec<VecDeque<WorkItem>> = Vec::new();
+    let mut queue_of = HashMap::new();
+    for item in items {
+        let index = *queue_of.entry(item.addr.ip()).or_insert_with(|| {
+            queues.push(VecDeque::new());
+            queues.len() - 1
+        });
+        queues[index].push_back(item);
+    }
+
+    let mut ordered = Vec::with_capacity(total);
+    while !queues.is_empty() {
+        queues.retain_mut(|queue| {
+            ordered.extend(queue.pop_front());
+            !queue.is_empty()
+        });
+    }
+    ordered
+}
+
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `n`, without the bias of taking the remainder
+    fn below(&mut self, n: usize) -> usize {
+        ((u128::from(self.next()) * n as u128) >> 64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::Arc;
+
+    fn items(hosts: &[&str], ports: std::ops::RangeInclusive<u16>) -> Vec<WorkItem> {
+        hosts
+            .iter()
+            .flat_map(|host| {
+                let ip: IpAddr = host.parse().unwrap();
+                ports.clone().map(move |port| WorkItem {
+                    host: Arc::from(*host),
+                    addr: SocketAddr::new(ip, port),
+                    tcp: true,
+                    udp: false,
+                })
+            })
+            .collect()
+    }
+
+    fn addrs(items: &[WorkItem]) -> Vec<SocketAddr> {
+        items.iter().map(|item| item.addr).collect()
+    }
+
+    #[test]
+    fn test_shuffle_is_a_reproducible_permutation() {
+        let original = items(&["10.0.0.1", "10.0.0.2"], 1..=100);
+        let order = ScanOrder { seed: Some(42), interleave: false };
+        let shuffled = order.apply(original.clone());
+        assert_ne!(addrs(&shuffled), addrs(&original));
+        assert_eq!(addrs(&order.apply(original.clone())), addrs(&shuffled));
+
+        let mut sorted = addrs(&shuffled);
+        sorted.sort();
+        assert_eq!(sorted, addrs(&original));
+
+        let other = ScanOrder { seed: Some(43), interleave: false }.apply(original.clone());
+        assert_ne!(addrs(&other), addrs(&shuffled));
+        // The default order leaves the items alone
+        assert_eq!(addrs(&ScanOrder::default().apply(original.clone())), addrs(&original));
+    }
+
+    #[test]
+    fn test_interleave_takes_hosts_in_turn() {
+        let mut original = items(&["10.0.0.1", "10.0.0.2"], 1..=3);
+        original.extend(items(&["10.0.0.3"], 1..=1));
+        let interleaved = ScanOrder { seed: None, interleave: true }.apply(original);
+        let expected: Vec<SocketAddr> = ["10.0.0.1:1", "10.0.0.2:1", "10.0.0.3:1", "10.0.0.1:2", "10.0.0.2:2", "10.0.0.1:3", "10.0.0.2:3"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+        assert_eq!(addrs(&interleaved), expected);
+
+        // Shuffled and interleaved, hosts still alternate
+        let shuffled = ScanOrder { seed: Some(7), interleave: true }.apply(items(&["10.0.0.1", "10.0.0.2"], 1..=50));
+        assert!(shuffled.windows(2).all(|pair| pair[0].addr.ip() != pair[1].addr.ip()));
+        assert_ne!(shuffled.iter().take(10).map(|item| item.addr.port()).collect::<Vec<_>>(), [1, 1, 2, 2, 3, 3, 4, 4, 5, 5]);
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::targets::{read_input, strip_comment};
+
+/// The ports to probe for each protocol. Saved as port lists such as
+/// `"1-1024,8080"`, so a full port range stays short in reports and checkpoints.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortSpec {
+    #[serde(serialize_with = "serialize_ports", deserialize_with = "deserialize_ports")]
+    pub tcp: Vec<u16>,
+    #[serde(serialize_with = "serialize_ports", deserialize_with = "deserialize_ports")]
+    pub udp: Vec<u16>,
+}
+
+impl PortSpec {
+    /// Probes the same ports over both TCP and UDP
+    pub fn both(ports: Vec<u16>) -> Self {
+        Self { tcp: ports.clone(), udp: ports }
+    }
+
+    /// Probes every port in an inclusive range over both TCP and UDP
+    pub fn range(start: u16, end: u16) -> Self {
+        Self::both((start..=end).collect())
+    }
+
+    /// Every distinct port in the spec, TCP ports first, in order
+    pub fn all_ports(&self) -> Vec<u16> {
+        let mut seen = HashSet::new();
+        self.tcp
+            .iter()
+            .chain(self.udp.iter())
+            .copied()
+            .filter(|p| seen.insert(*p))
+            .collect()
+    }
+
+    /// Keeps only the ports of the selected protocols.
+    /// Selecting neither protocol keeps both, like passing neither `--tcp` nor `--udp`.
+    pub fn select_protocols(&mut self, tcp: bool, udp: bool) {
+        if tcp || udp {
+            if !tcp {
+                self.tcp.clear();
+            }
+            if !udp {
+                self.udp.clear();
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tcp.is_empty() && self.udp.is_empty()
+    }
+
+    /// Adds the ports of `other` that are not already present
+    pub fn merge(&mut self, other: PortSpec) {
+        for (ours, theirs) in [(&mut self.tcp, other.tcp), (&mut self.udp, other.udp)] {
+            let mut seen: HashSet<u16> = ours.iter().copied().collect();
+            ours.extend(theirs.into_iter().filter(|p| seen.insert(*p)));
+        }
+    }
+}
+
+/// Parses a port specification such as `22,80,443,8000-8100` or `T:80,443,U:53,161`.
+/// - Entries are single ports or ranges; a range may omit either end (`-1024`, `60000-`)
+///   and a bare `-` means every port
+/// - Entries before any prefix apply to both protocols; `T:` and `U:` switch
+///   the following entries to TCP-only or UDP-only
+///
+/// Duplicate ports are removed while preserving order.
+pub fn parse_port_spec(spec: &str) -> Result<PortSpec, String> {
+    let mut tcp = OrderedPorts::default();
+    let mut udp = OrderedPorts::default();
+    let (mut want_tcp, mut want_udp) = (true, true);
+
+    for part in spec.split(',') {
+        let mut part = part.trim();
+
+        if let Some(rest) = strip_prefix_ignore_case(part, "T:") {
+            (want_tcp, want_udp) = (true, false);
+            part = rest.trim();
+        } else if let Some(rest) = strip_prefix_ignore_case(part, "U:") {
+            (want_tcp, want_udp) = (false, true);
+            part = rest.trim();
+        }
+
+        if part.is_empty() {
+            continue;
+        }
+
+        let (start, end) = parse_entry(part)?;
+        if want_tcp {
+            tcp.extend(start..=end);
+        }
+        if want_udp {
+            udp.extend(start..=end);
+        }
+    }
+
+    let spec = PortSpec { tcp: tcp.ports, udp: udp.ports };
+    if spec.is_empty() {
+        return Err("No ports specified".to_string());
+    }
+
+    Ok(spec)
+}
+
+/// Parses a port list, one port specification per line.
+/// Blank lines and `#` comments are ignored.
+pub fn parse_port_lines(text: &str) -> Result<PortSpec, String> {
+    let mut spec = PortSpec::default();
+
+    for (line_no, line) in text.lines().enumerate() {
+        let line = strip_comment(line);
+        if line.is_empty() {
+            continue;
+        }
+
+        let line_spec = parse_port_spec(line)
+            .map_err(|e| format!("line {}: {}", line_no + 1, e))?;
+        spec.merge(line_spec);
+    }
+
+    if spec.is_empty() {
+        return Err("Port list is empty".to_string());
+    }
+
+    Ok(spec)
+}
+
+/// Reads a port list from a file, or from stdin when `path` is `-`
+pub fn read_port_file(path: &str) -> Result<PortSpec, String> {
+    let text = read_input(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    parse_port_lines(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+#[derive(Default)]
+struct OrderedPorts {
+    ports: Vec<u16>,
+    seen: HashSet<u16>,
+}
+
+impl OrderedPorts {
+    fn extend(&mut self, ports: impl Iterator<Item = u16>) {
+        for port in ports {
+            if self.seen.insert(port) {
+                self.ports.push(port);
+            }
+        }
+    }
+}
+
+/// Writes ports in order, joining runs of consecutive ports into ranges
+fn format_ports(ports: &[u16]) -> String {
+    let mut entries = Vec::new();
+    let mut i = 0;
+    while i < ports.len() {
+        let start = ports[i];
+        let mut end = start;
+        while i + 1 < ports.len() && end < u16::MAX && ports[i + 1] == end + 1 {
+            end += 1;
+            i += 1;
+        }
+        entries.push(if start == end { start.to_string() } else { format!("{}-{}", start, end) });
+        i += 1;
+    }
+    entries.join(",")
+}
+
+fn serialize_ports<S: Serializer>(ports: &[u16], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_ports(ports))
+}
+
+fn deserialize_ports<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u16>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    let mut ports = OrderedPorts::default();
+    for part in text.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        let (start, end) = parse_entry(part).map_err(serde::de::Error::custom)?;
+        ports.extend(start..=end);
+    }
+    Ok(ports.ports)
+}
+
+/// Parses a single port or range into inclusive bounds
+fn parse_entry(part: &str) -> Result<(u16, u16), String> {
+    let (start, end) = match part.split_once('-') {
+        Some((start, end)) => {
+            let start = if start.trim().is_empty() { 1 } else { parse_port(start)? };
+            let end = if end.trim().is_empty() { u16::MAX } else { parse_port(end)? };
+            (start, end)
+        }
+        None => {
+            let port = parse_port(part)?;
+            (port, port)
+        }
+    };
+
+    if end < start {
+        return Err(format!("Invalid port range: {}", part));
+    }
+
+    Ok((start, end))
+}
+
+/// Parses a single port number between 1 and 65535
+pub fn parse_port(port: &str) -> Result<u16, String> {
+    match port.trim().parse::<u16>() {
+        Ok(p) if p > 0 => Ok(p),
+        _ => Err(format!("Invalid port: {}", port.trim())),
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    if s.len() >= prefix.len() && s[..prefix.len()].eq_ignore_ascii_case(prefix) {
+        Some(&s[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_port_spec() {
+        let spec = parse_port_spec("22,80,443,8000-8002").unwrap();
+        assert_eq!(spec.tcp, vec![22, 80, 443, 8000, 8001, 8002]);
+        assert_eq!(spec.tcp, spec.udp);
+
+        assert_eq!(parse_port_spec("8000-8003, 22,8001").unwrap().tcp, vec![8000, 8001, 8002, 8003, 22]);
+        assert!(parse_port_spec("0").is_err());
+        assert!(parse_port_spec("90-80").is_err());
+        assert!(parse_port_spec("http").is_err());
+    }
+
+    #[test]
+    fn test_protocol_prefixes() {
+        let spec = parse_port_spec("53,T:80,443,U:161,t:22").unwrap();
+        assert_eq!(spec.tcp, vec![53, 80, 443, 22]);
+        assert_eq!(spec.udp, vec![53, 161]);
+        assert_eq!(spec.all_ports(), vec![53, 80, 443, 22, 161]);
+    }
+
+    #[test]
+    fn test_select_protocols() {
+        let mut spec = parse_port_spec("22,U:53").unwrap();
+        spec.select_protocols(false, false);
+        assert_eq!(spec, parse_port_spec("22,U:53").unwrap());
+
+        spec.select_protocols(true, false);
+        assert_eq!(spec.tcp, vec![22]);
+        assert!(spec.udp.is_empty());
+    }
+
+    #[test]
+    fn test_open_ranges() {
+        assert_eq!(parse_port_spec("-").unwrap().tcp.len(), 65535);
+        assert_eq!(parse_port_spec("-3").unwrap().tcp, vec![1, 2, 3]);
+        assert_eq!(parse_port_spec("65534-").unwrap().tcp, vec![65534, 65535]);
+        let spec = parse_port_spec("U:-").unwrap();
+        assert!(spec.tcp.is_empty());
+        assert_eq!(spec.udp.len(), 65535);
+    }
+
+    #[test]
+    fn test_parse_port_lines() {
+        let text = "# web\n80\n443 # tls\n\n8000-8002\nU:53\n80\n";
+        let spec = parse_port_lines(text).unwrap();
+        assert_eq!(spec.tcp, vec![80, 443, 8000, 8001, 8002]);
+        assert_eq!(spec.udp, vec![80, 443, 8000, 8001, 8002, 53]);
+        assert!(parse_port_lines("# nothing\n").is_err());
+    }
+
+    #[test]
+    fn test_port_spec_serializes_as_ranges() {
+        let spec = parse_port_spec("T:8080,1-1024,65535,U:53").unwrap();
+        let json = serde_json::to_string(&spec).unwrap();
+        assert_eq!(json, r#"{"tcp":"8080,1-1024,65535","udp":"53"}"#);
+        assert_eq!(serde_json::from_str::<PortSpec>(&json).unwrap(), spec);
+        assert!(serde_json::from_str::<PortSpec>(r#"{"tcp":"0","udp":""}"#).is_err());
+    }
+}
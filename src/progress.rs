@@ -3,13 +3,16 @@ use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
-/// Prints the progress in terminal
+use tokio_util::sync::CancellationToken;
+
+/// Prints the progress in terminal until every probe is done or the scan is cancelled
 pub fn spawn_reporter(
     total: usize,
     completed: Arc<AtomicUsize>,
+    cancel: CancellationToken,
 ) -> thread::JoinHandle<()> {
     thread::spawn(move || {
-        while completed.load(Ordering::Relaxed) < total {
+        while completed.load(Ordering::Relaxed) < total && !cancel.is_cancelled() {
             let done = completed.load(Ordering::Relaxed);
             let pct = done as f64 * 100.0 / total as f64;
             println!("Progress: {}/{} ({:.1}%)", done, total, pct);
@@ -1,6 +1,8 @@
 use std::io::{Read, Write};
-use std::net::{SocketAddr, TcpStream};
-use std::time::Duration;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::connector::Connector;
+use crate::timing::Timing;
 
 #[derive(Debug, Clone)]
 pub struct HttpResponse {
@@ -10,24 +12,22 @@ pub struct HttpResponse {
     pub body_preview: String,
 }
 
-/// Sends an HTTP HEAD request and parses the response headers
-pub fn probe_http(addr: &str, port: u16, is_https: bool) -> Option<HttpResponse> {
+/// Sends an HTTP HEAD request and parses the response headers.
+/// `host` is the name the target was given as, used for the Host header.
+pub fn probe_http(addr: SocketAddr, host: &str, is_https: bool, timing: &Timing, connector: &Connector) -> Option<HttpResponse> {
     if is_https {
         // For HTTPS, we'll handle this in the TLS module
         return None;
     }
 
-    let socket_str = format!("{}:{}", addr, port);
-    let socket_addr: SocketAddr = socket_str.parse().ok()?;
-
-    let mut stream = TcpStream::connect_timeout(&socket_addr, Duration::from_millis(200)).ok()?;
-    stream.set_read_timeout(Some(Duration::from_millis(500))).ok()?;
-    stream.set_write_timeout(Some(Duration::from_millis(200))).ok()?;
+    let mut stream = connector.connect_timeout(addr, timing.probe_connect()).ok()?;
+    stream.set_read_timeout(Some(timing.probe_read())).ok()?;
+    stream.set_write_timeout(Some(timing.probe_read())).ok()?;
 
     // Send HTTP HEAD request
     let request = format!(
         "HEAD / HTTP/1.1\r\nHost: {}\r\nUser-Agent: port-scanner/0.1\r\nConnection: close\r\n\r\n",
-        addr
+        host_header(host, addr.port())
     );
 
     stream.write_all(request.as_bytes()).ok()?;
@@ -43,6 +43,21 @@ pub fn probe_http(addr: &str, port: u16, is_https: bool) -> Option<HttpResponse>
     parse_http_response(&response_text)
 }
 
+/// Builds the Host header value, bracketing IPv6 literals and
+/// appending the port when it is not the HTTP default
+fn host_header(host: &str, port: u16) -> String {
+    let host = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(v6)) => format!("[{}]", v6),
+        _ => host.to_string(),
+    };
+
+    if port == 80 {
+        host
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
 /// Parses HTTP response text into structured data
 fn parse_http_response(response: &str) -> Option<HttpResponse> {
     let mut lines = response.lines();
@@ -92,4 +107,11 @@ mod tests {
         assert_eq!(parsed.status_line, "HTTP/1.1 200 OK");
         assert_eq!(parsed.server, Some("nginx/1.18.0".to_string()));
     }
+
+    #[test]
+    fn test_host_header() {
+        assert_eq!(host_header("example.com", 80), "example.com");
+        assert_eq!(host_header("example.com", 8080), "example.com:8080");
+        assert_eq!(host_header("::1", 8000), "[::1]:8000");
+    }
 }
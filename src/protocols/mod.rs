@@ -2,3 +2,4 @@ pub mod http;
 pub mod tls;
 pub mod ssh;
 pub mod smtp_ftp;
+pub mod udp_probes;
@@ -1,38 +1,37 @@
 use std::io::Read;
-use std::net::{SocketAddr, TcpStream};
-use std::time::Duration;
+use std::net::SocketAddr;
+
+use crate::connector::Connector;
+use crate::timing::Timing;
 
 /// Probes for SMTP greeting banner
 /// SMTP servers send a 220 greeting immediately upon connection
-pub fn probe_smtp(addr: &str, port: u16) -> Option<String> {
-    read_greeting(addr, port)
+pub fn probe_smtp(addr: SocketAddr, timing: &Timing, connector: &Connector) -> Option<String> {
+    read_greeting(addr, timing, connector)
 }
 
 /// Probes for FTP greeting banner
 /// FTP servers send a 220 greeting immediately upon connection
-pub fn probe_ftp(addr: &str, port: u16) -> Option<String> {
-    read_greeting(addr, port)
+pub fn probe_ftp(addr: SocketAddr, timing: &Timing, connector: &Connector) -> Option<String> {
+    read_greeting(addr, timing, connector)
 }
 
 /// Probes for POP3 greeting banner
 /// POP3 servers send a +OK greeting immediately upon connection
-pub fn probe_pop3(addr: &str, port: u16) -> Option<String> {
-    read_greeting(addr, port)
+pub fn probe_pop3(addr: SocketAddr, timing: &Timing, connector: &Connector) -> Option<String> {
+    read_greeting(addr, timing, connector)
 }
 
 /// Probes for IMAP greeting banner
 /// IMAP servers send an untagged OK greeting immediately upon connection
-pub fn probe_imap(addr: &str, port: u16) -> Option<String> {
-    read_greeting(addr, port)
+pub fn probe_imap(addr: SocketAddr, timing: &Timing, connector: &Connector) -> Option<String> {
+    read_greeting(addr, timing, connector)
 }
 
 /// Generic function to read greeting from servers that speak first
-fn read_greeting(addr: &str, port: u16) -> Option<String> {
-    let socket_str = format!("{}:{}", addr, port);
-    let socket_addr: SocketAddr = socket_str.parse().ok()?;
-
-    let mut stream = TcpStream::connect_timeout(&socket_addr, Duration::from_millis(300)).ok()?;
-    stream.set_read_timeout(Some(Duration::from_millis(1000))).ok()?;
+fn read_greeting(addr: SocketAddr, timing: &Timing, connector: &Connector) -> Option<String> {
+    let mut stream = connector.connect_timeout(addr, timing.probe_connect()).ok()?;
+    stream.set_read_timeout(Some(timing.probe_read())).ok()?;
 
     // These protocols send greeting immediately, no need to send anything
     let mut buffer = [0u8; 512];
@@ -1,6 +1,8 @@
 use std::io::Read;
-use std::net::{SocketAddr, TcpStream};
-use std::time::Duration;
+use std::net::SocketAddr;
+
+use crate::connector::Connector;
+use crate::timing::Timing;
 
 #[derive(Debug, Clone)]
 pub struct SshBanner {
@@ -11,12 +13,9 @@ pub struct SshBanner {
 
 /// Reads SSH banner from an open SSH port
 /// SSH servers send their banner immediately upon connection
-pub fn probe_ssh(addr: &str, port: u16) -> Option<SshBanner> {
-    let socket_str = format!("{}:{}", addr, port);
-    let socket_addr: SocketAddr = socket_str.parse().ok()?;
-
-    let mut stream = TcpStream::connect_timeout(&socket_addr, Duration::from_millis(200)).ok()?;
-    stream.set_read_timeout(Some(Duration::from_millis(500))).ok()?;
+pub fn probe_ssh(addr: SocketAddr, timing: &Timing, connector: &Connector) -> Option<SshBanner> {
+    let mut stream = connector.connect_timeout(addr, timing.probe_connect()).ok()?;
+    stream.set_read_timeout(Some(timing.probe_read())).ok()?;
 
     // SSH servers send banner immediately, no need to send anything
     let mut buffer = [0u8; 256];
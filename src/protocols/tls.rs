@@ -1,27 +1,24 @@
 use std::io::{Read, Write};
-use std::net::TcpStream;
-use std::time::Duration;
-
+use std::net::{IpAddr, SocketAddr};
+use crate::connector::Connector;
 use crate::service_info::TlsInfo;
+use crate::timing::Timing;
 
 /// Attempts a basic TLS detection by looking for TLS handshake response
 /// NOTE: This is a simplified version. For full certificate extraction,
 /// you would need to add rustls or native-tls as dependencies.
-pub fn probe_tls(addr: &str, port: u16) -> Option<TlsInfo> {
-    let socket_addr = format!("{}:{}", addr, port);
-
+/// `host` is the name the target was given as; DNS names are sent as SNI.
+pub fn probe_tls(addr: SocketAddr, host: &str, timing: &Timing, connector: &Connector) -> Option<TlsInfo> {
     // Connect to the server
-    let mut stream = TcpStream::connect_timeout(
-        &socket_addr.parse().ok()?,
-        Duration::from_millis(1000)
-    ).ok()?;
+    let mut stream = connector.connect_timeout(addr, timing.probe_connect()).ok()?;
 
-    stream.set_read_timeout(Some(Duration::from_millis(2000))).ok()?;
-    stream.set_write_timeout(Some(Duration::from_millis(1000))).ok()?;
+    stream.set_read_timeout(Some(timing.probe_read())).ok()?;
+    stream.set_write_timeout(Some(timing.probe_read())).ok()?;
 
     // Send a TLS ClientHello (simplified)
-    // This is a minimal TLS 1.2 ClientHello packet
-    let client_hello = create_simple_client_hello();
+    // This is a minimal TLS 1.2 ClientHello packet, with SNI for virtual-hosted servers
+    let server_name = if host.parse::<IpAddr>().is_ok() { None } else { Some(host) };
+    let client_hello = create_simple_client_hello(server_name);
 
     if stream.write_all(&client_hello).is_err() {
         return None;
@@ -55,20 +52,12 @@ pub fn probe_tls(addr: &str, port: u16) -> Option<TlsInfo> {
     }
 }
 
-/// Creates a minimal TLS ClientHello for detection purposes
-fn create_simple_client_hello() -> Vec<u8> {
+/// Creates a minimal TLS ClientHello for detection purposes,
+/// optionally carrying a server_name (SNI) extension
+fn create_simple_client_hello(server_name: Option<&str>) -> Vec<u8> {
     // A proper minimal TLS 1.2 ClientHello
     // This is the smallest valid ClientHello that will trigger a ServerHello response
-    vec![
-        // TLS Record Header
-        0x16,       // Content Type: Handshake
-        0x03, 0x01, // Version: TLS 1.0 (for compatibility)
-        0x00, 0x3e, // Length: 62 bytes
-
-        // Handshake Header
-        0x01,             // Handshake Type: ClientHello
-        0x00, 0x00, 0x3a, // Length: 58 bytes
-
+    let mut hello = vec![
         // ClientHello
         0x03, 0x03, // Version: TLS 1.2
 
@@ -87,10 +76,36 @@ fn create_simple_client_hello() -> Vec<u8> {
         // Compression Methods
         0x01, // Length: 1
         0x00, // Compression: null
+    ];
+
+    // Extensions
+    let mut extensions = Vec::new();
+    if let Some(name) = server_name {
+        let name = name.as_bytes();
+        let name_len = name.len() as u16;
+        extensions.extend_from_slice(&[0x00, 0x00]);                    // Extension Type: server_name
+        extensions.extend_from_slice(&(name_len + 5).to_be_bytes());    // Extension Length
+        extensions.extend_from_slice(&(name_len + 3).to_be_bytes());    // Server Name List Length
+        extensions.push(0x00);                                          // Name Type: host_name
+        extensions.extend_from_slice(&name_len.to_be_bytes());          // Name Length
+        extensions.extend_from_slice(name);
+    }
+    hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+    hello.extend_from_slice(&extensions);
 
-        // Extensions Length: 0
-        0x00, 0x00,
-    ]
+    // Handshake Header
+    let mut handshake = vec![0x01]; // Handshake Type: ClientHello
+    handshake.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+    handshake.extend_from_slice(&hello);
+
+    // TLS Record Header
+    let mut record = vec![
+        0x16,       // Content Type: Handshake
+        0x03, 0x01, // Version: TLS 1.0 (for compatibility)
+    ];
+    record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+    record.extend_from_slice(&handshake);
+    record
 }
 
 /// Checks if a port is likely to use TLS based on port number
@@ -109,4 +124,18 @@ mod tests {
         assert!(!is_likely_tls_port(80));
         assert!(!is_likely_tls_port(22));
     }
+
+    #[test]
+    fn test_client_hello_lengths() {
+        // Record and handshake lengths must match the bytes that follow them
+        let hello = create_simple_client_hello(None);
+        assert_eq!(hello.len(), 52);
+        assert_eq!(&hello[3..5], &[0x00, 0x2f]);
+        assert_eq!(&hello[6..9], &[0x00, 0x00, 0x2b]);
+
+        let hello = create_simple_client_hello(Some("example.com"));
+        assert_eq!(hello.len(), 52 + 4 + 5 + 11);
+        assert_eq!(u16::from_be_bytes([hello[3], hello[4]]) as usize, hello.len() - 5);
+        assert!(hello.ends_with(b"example.com"));
+    }
 }
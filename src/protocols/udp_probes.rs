@@ -0,0 +1,297 @@
+// Protocol-specific UDP payloads, keyed by well-known port.
+// Most UDP services ignore an empty datagram, so each probe sends a request
+// the service is expected to answer, and replies are parsed to name the service.
+
+/// DNS: CHAOS TXT query for `version.bind`, ID 0x1234, recursion desired
+const DNS_VERSION_BIND: &[u8] = &[
+    0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x07, b'v', b'e', b'r', b's', b'i', b'o', b'n',
+    0x04, b'b', b'i', b'n', b'd', 0x00,
+    0x00, 0x10, // Type: TXT
+    0x00, 0x03, // Class: CHAOS
+];
+
+/// NTP: version 4 client request (LI=3, VN=4, Mode=3), remaining fields zero
+const NTP_CLIENT: &[u8] = &[
+    0xe3, 0x00, 0x04, 0xfa, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// SNMPv1 get-request for sysDescr.0 with community `public`
+const SNMP_GET_SYSDESCR: &[u8] = &[
+    0x30, 0x29,                                     // Message
+    0x02, 0x01, 0x00,                               // Version: 1
+    0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', // Community: public
+    0xa0, 0x1c,                                     // GetRequest PDU
+    0x02, 0x04, 0x71, 0xb4, 0x1d, 0x2a,             // Request ID
+    0x02, 0x01, 0x00,                               // Error status
+    0x02, 0x01, 0x00,                               // Error index
+    0x30, 0x0e, 0x30, 0x0c,                         // Varbind list / varbind
+    0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, // OID 1.3.6.1.2.1.1.1.0
+    0x05, 0x00,                                     // Value: NULL
+];
+
+/// sysDescr.0 OID as encoded in SNMP messages
+const SYSDESCR_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00];
+
+/// SSDP: discovery request for all devices and services
+const SSDP_MSEARCH: &[u8] = b"M-SEARCH * HTTP/1.1\r\n\
+HOST: 239.255.255.250:1900\r\n\
+MAN: \"ssdp:discover\"\r\n\
+MX: 1\r\n\
+ST: ssdp:all\r\n\r\n";
+
+/// NetBIOS: node status (NBSTAT) query for the wildcard name `*`
+const NETBIOS_NBSTAT: &[u8] = b"\x80\xf0\x00\x10\x00\x01\x00\x00\x00\x00\x00\x00\
+\x20CKAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\x00\x00\x21\x00\x01";
+
+/// Memcached: UDP frame header (request 1, sequence 0, 1 datagram) followed by `stats`
+const MEMCACHED_STATS: &[u8] = b"\x00\x01\x00\x00\x00\x01\x00\x00stats\r\n";
+
+/// A service identified from a UDP reply
+#[derive(Debug, Clone, PartialEq)]
+pub struct UdpServiceInfo {
+    pub service: String,
+    pub version: Option<String>,
+    pub banner: Option<String>,
+    pub confidence: f32,
+}
+
+/// Returns the probe payload for a port, or an empty datagram when no
+/// protocol-specific payload is known
+pub fn payload_for_port(port: u16) -> &'static [u8] {
+    match port {
+        53 => DNS_VERSION_BIND,
+        123 => NTP_CLIENT,
+        137 => NETBIOS_NBSTAT,
+        161 => SNMP_GET_SYSDESCR,
+        1900 => SSDP_MSEARCH,
+        11211 => MEMCACHED_STATS,
+        _ => &[],
+    }
+}
+
+/// Parses a reply to the probe sent by `payload_for_port`
+pub fn identify_reply(port: u16, reply: &[u8]) -> Option<UdpServiceInfo> {
+    match port {
+        53 => parse_dns(reply),
+        123 => parse_ntp(reply),
+        137 => parse_netbios(reply),
+        161 => parse_snmp(reply),
+        1900 => parse_ssdp(reply),
+        11211 => parse_memcached(reply),
+        _ => None,
+    }
+}
+
+fn parse_dns(reply: &[u8]) -> Option<UdpServiceInfo> {
+    // Same transaction ID and the QR (response) bit set
+    if reply.len() < 12 || reply[0..2] != DNS_VERSION_BIND[0..2] || reply[2] & 0x80 == 0 {
+        return None;
+    }
+
+    // A single TXT answer carries the version string at the end of the message
+    let answers = u16::from_be_bytes([reply[6], reply[7]]);
+    let version = if answers > 0 && reply.len() > DNS_VERSION_BIND.len() {
+        last_character_string(reply)
+    } else {
+        None
+    };
+
+    Some(UdpServiceInfo {
+        service: "DNS".to_string(),
+        banner: version.clone().map(|v| format!("version.bind: {}", v)),
+        version,
+        confidence: 0.9,
+    })
+}
+
+/// Extracts a length-prefixed string that ends exactly at the end of the message
+fn last_character_string(message: &[u8]) -> Option<String> {
+    (1..=255usize.min(message.len() - 1)).rev().find_map(|len| {
+        let start = message.len() - len - 1;
+        (message[start] as usize == len)
+            .then(|| printable(&message[start + 1..]))
+            .flatten()
+    })
+}
+
+fn parse_ntp(reply: &[u8]) -> Option<UdpServiceInfo> {
+    // Mode 4 is a server response
+    if reply.len() < 48 || reply[0] & 0x07 != 4 {
+        return None;
+    }
+
+    let version = (reply[0] >> 3) & 0x07;
+    let stratum = reply[1];
+    Some(UdpServiceInfo {
+        service: "NTP".to_string(),
+        version: Some(version.to_string()),
+        banner: Some(format!("NTPv{} stratum {}", version, stratum)),
+        confidence: 0.9,
+    })
+}
+
+fn parse_netbios(reply: &[u8]) -> Option<UdpServiceInfo> {
+    // Same transaction ID and the response bit set
+    if reply.len() < 12 || reply[0..2] != NETBIOS_NBSTAT[0..2] || reply[2] & 0x80 == 0 {
+        return None;
+    }
+
+    // Header (12) + name (34) + type, class, TTL, rdlength (10) precede the name count;
+    // each name entry is a 15-byte space-padded name, a suffix byte and 2 flag bytes
+    let name = reply
+        .get(57..72)
+        .and_then(printable)
+        .map(|n| n.trim().to_string())
+        .filter(|n| !n.is_empty());
+
+    Some(UdpServiceInfo {
+        service: "NetBIOS-NS".to_string(),
+        version: None,
+        banner: name.map(|n| format!("Name: {}", n)),
+        confidence: 0.9,
+    })
+}
+
+fn parse_snmp(reply: &[u8]) -> Option<UdpServiceInfo> {
+    if reply.first() != Some(&0x30) {
+        return None;
+    }
+
+    // Find the sysDescr value: OID followed by an OCTET STRING
+    let descr = reply
+        .windows(SYSDESCR_OID.len())
+        .position(|w| w == SYSDESCR_OID)
+        .and_then(|pos| {
+            let value = &reply[pos + SYSDESCR_OID.len()..];
+            if value.first() != Some(&0x04) {
+                return None;
+            }
+            let (len, start) = match *value.get(1)? {
+                0x81 => (*value.get(2)? as usize, 3),
+                len if len < 0x80 => (len as usize, 2),
+                _ => return None,
+            };
+            printable(value.get(start..start + len)?)
+        });
+
+    Some(UdpServiceInfo {
+        service: "SNMP".to_string(),
+        version: None,
+        banner: descr,
+        confidence: 0.9,
+    })
+}
+
+fn parse_ssdp(reply: &[u8]) -> Option<UdpServiceInfo> {
+    let text = String::from_utf8_lossy(reply);
+    if !text.starts_with("HTTP/1.1 200") && !text.starts_with("NOTIFY") {
+        return None;
+    }
+
+    let server = text
+        .lines()
+        .find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim().eq_ignore_ascii_case("server").then(|| value.trim().to_string())
+        });
+
+    Some(UdpServiceInfo {
+        service: "SSDP".to_string(),
+        version: None,
+        banner: server,
+        confidence: 0.9,
+    })
+}
+
+fn parse_memcached(reply: &[u8]) -> Option<UdpServiceInfo> {
+    // Skip the 8-byte UDP frame header
+    let text = String::from_utf8_lossy(reply.get(8..)?);
+    if !text.starts_with("STAT ") {
+        return None;
+    }
+
+    let version = text
+        .lines()
+        .find_map(|line| line.strip_prefix("STAT version "))
+        .map(|v| v.trim().to_string());
+
+    Some(UdpServiceInfo {
+        service: "Memcached".to_string(),
+        version,
+        banner: Some("STAT response".to_string()),
+        confidence: 0.95,
+    })
+}
+
+/// Converts bytes to a string if they are printable ASCII
+fn printable(bytes: &[u8]) -> Option<String> {
+    if !bytes.is_empty() && bytes.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        Some(String::from_utf8_lossy(bytes).to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_for_port() {
+        assert_eq!(payload_for_port(123).len(), 48);
+        assert_eq!(payload_for_port(161).len(), 2 + 0x29);
+        assert_eq!(payload_for_port(137).len(), 50);
+        assert!(payload_for_port(9999).is_empty());
+    }
+
+    #[test]
+    fn test_parse_ntp() {
+        let mut reply = vec![0u8; 48];
+        reply[0] = 0x24; // LI=0, VN=4, Mode=4
+        reply[1] = 2;
+        let info = identify_reply(123, &reply).unwrap();
+        assert_eq!(info.service, "NTP");
+        assert_eq!(info.banner.as_deref(), Some("NTPv4 stratum 2"));
+
+        // Our own client request echoed back is not a server reply
+        assert!(identify_reply(123, NTP_CLIENT).is_none());
+    }
+
+    #[test]
+    fn test_parse_snmp() {
+        let mut reply = vec![0x30, 0x30, 0x02, 0x01, 0x00];
+        reply.extend_from_slice(&[0x06, 0x08]);
+        reply.extend_from_slice(SYSDESCR_OID);
+        reply.extend_from_slice(&[0x04, 0x05]);
+        reply.extend_from_slice(b"Linux");
+        let info = identify_reply(161, &reply).unwrap();
+        assert_eq!(info.service, "SNMP");
+        assert_eq!(info.banner.as_deref(), Some("Linux"));
+    }
+
+    #[test]
+    fn test_parse_dns_version() {
+        let mut reply = DNS_VERSION_BIND.to_vec();
+        reply[2] = 0x85;
+        reply[7] = 1; // One answer
+        reply.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x10, 0x00, 0x03, 0, 0, 0, 0, 0x00, 0x07, 0x06]);
+        reply.extend_from_slice(b"9.18.1");
+        let info = identify_reply(53, &reply).unwrap();
+        assert_eq!(info.service, "DNS");
+        assert_eq!(info.version.as_deref(), Some("9.18.1"));
+    }
+
+    #[test]
+    fn test_parse_memcached_and_ssdp() {
+        let reply = b"\x00\x01\x00\x00\x00\x01\x00\x00STAT pid 1\r\nSTAT version 1.6.21\r\nEND\r\n";
+        assert_eq!(identify_reply(11211, reply).unwrap().version.as_deref(), Some("1.6.21"));
+
+        let reply = b"HTTP/1.1 200 OK\r\nSERVER: Linux/5.4 UPnP/1.0 MiniUPnPd/2.2\r\n\r\n";
+        let info = identify_reply(1900, reply).unwrap();
+        assert_eq!(info.banner.as_deref(), Some("Linux/5.4 UPnP/1.0 MiniUPnPd/2.2"));
+    }
+}
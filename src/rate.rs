@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Global probe rate limit: hands out one slot every `1 / rate` seconds,
+/// so probes are spread evenly instead of sent in bursts
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// `rate` is in probes per second and must be non-zero
+    pub fn new(rate: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / rate.max(1),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits until `n` probes may be sent together: takes the next `n` slots and
+    /// returns at the last of them, so the probes never run ahead of the rate
+    pub async fn acquire_n(&self, n: u32) {
+        let last_slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let first_slot = (*next_slot).max(Instant::now());
+            *next_slot = first_slot + self.interval * n;
+            first_slot + self.interval * n.saturating_sub(1)
+        };
+        tokio::time::sleep_until(last_slot).await;
+    }
+}
+
+/// Caps the number of work items in flight against any single host. The scan
+/// dispatcher owns it and asks before sending each item, so an item of a host at
+/// its cap is held back without stopping the items of other hosts.
+#[derive(Debug)]
+pub struct HostLimiter {
+    max_per_host: usize,
+    in_flight: HashMap<IpAddr, usize>,
+}
+
+impl HostLimiter {
+    pub fn new(max_per_host: usize) -> Self {
+        Self {
+            max_per_host: max_per_host.max(1),
+            in_flight: HashMap::new(),
+        }
+    }
+
+    /// Takes a slot for `ip` if it has a free one; give it back with `release`
+    pub fn try_acquire(&mut self, ip: IpAddr) -> bool {
+        let in_flight = self.in_flight.entry(ip).or_insert(0);
+        if *in_flight >= self.max_per_host {
+            return false;
+        }
+        *in_flight += 1;
+        true
+    }
+
+    pub fn release(&mut self, ip: IpAddr) {
+        if let Some(in_flight) = self.in_flight.get_mut(&ip) {
+            *in_flight -= 1;
+            if *in_flight == 0 {
+                self.in_flight.remove(&ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rate_limiter_spaces_probes() {
+        let limiter = RateLimiter::new(200);
+        let started = Instant::now();
+        for _ in 0..21 {
+            limiter.acquire_n(1).await;
+        }
+        // 21 probes at 200/s take at least 20 intervals of 5ms
+        assert!(started.elapsed() >= Duration::from_millis(100), "{:?}", started.elapsed());
+
+        // Taking several slots at once waits for the last of them, and the next
+        // probe comes one interval after it
+        let started = Instant::now();
+        limiter.acquire_n(10).await;
+        limiter.acquire_n(1).await;
+        assert!(started.elapsed() >= Duration::from_millis(50), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn test_host_limiter_caps_each_host() {
+        let mut limiter = HostLimiter::new(2);
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert!(limiter.try_acquire(a));
+        assert!(limiter.try_acquire(a));
+        assert!(!limiter.try_acquire(a));
+
+        // Other hosts are unaffected, and releasing a slot lets the next probe in
+        assert!(limiter.try_acquire(b));
+        limiter.release(a);
+        assert!(limiter.try_acquire(a));
+        assert!(!limiter.try_acquire(a));
+    }
+}
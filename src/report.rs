@@ -0,0 +1,270 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint::Checkpoint;
+use crate::discovery::HostStatus;
+use crate::ports::PortSpec;
+use crate::service_info::{PortState, Protocol, ServiceInfo};
+use crate::targets::{unique_addresses, unique_hosts, ResolvedTarget};
+
+/// The results of a scan together with what was scanned, as saved by `scan --output`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanReport {
+    /// Every scanned (host, address) pair, in scan order
+    pub addresses: Vec<(String, IpAddr)>,
+    /// The resolved targets, including any pinned ports; empty in older reports
+    #[serde(default)]
+    pub targets: Vec<ResolvedTarget>,
+    /// The ports probed on targets without a pinned port; `None` in older reports
+    #[serde(default)]
+    pub ports: Option<PortSpec>,
+    /// Whether closed and filtered ports were reported
+    pub show_closed: bool,
+    pub total_probes: usize,
+    pub completed_probes: usize,
+    /// True if the scan was stopped before every probe was sent
+    pub cancelled: bool,
+    pub results: Vec<ServiceInfo>,
+    /// Host discovery results; empty if discovery was skipped
+    #[serde(default)]
+    pub discovery: Vec<HostStatus>,
+}
+
+impl ScanReport {
+    /// Distinct host names, in scan order
+    pub fn hosts(&self) -> Vec<String> {
+        unique_hosts(self.addresses.iter().map(|(host, _)| host.as_str()))
+    }
+
+    /// Hosts found down by discovery, whose ports were not scanned
+    pub fn down_hosts(&self) -> impl Iterator<Item = &HostStatus> {
+        self.discovery.iter().filter(|status| !status.up)
+    }
+
+    /// Loads a report, or the results so far of a checkpoint file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if let Ok(report) = serde_json::from_str::<ScanReport>(&data) {
+            return Ok(report);
+        }
+        match serde_json::from_str::<Checkpoint>(&data) {
+            Ok(checkpoint) => Ok(checkpoint.into()),
+            Err(_) => Err(format!("{}: not a scan report or checkpoint", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+impl From<Checkpoint> for ScanReport {
+    fn from(checkpoint: Checkpoint) -> Self {
+        ScanReport {
+            addresses: unique_addresses(&checkpoint.targets),
+            show_closed: checkpoint.options.show_closed,
+            total_probes: checkpoint.total_probes,
+            completed_probes: checkpoint.completed_probes(),
+            cancelled: !checkpoint.remaining.is_empty(),
+            targets: checkpoint.targets,
+            ports: checkpoint.ports,
+            results: checkpoint.results,
+            discovery: Vec::new(),
+        }
+    }
+}
+
+/// Open ports that differ between two scans
+#[derive(Debug, Default)]
+pub struct ScanDiff {
+    /// Open in the new scan only
+    pub opened: Vec<ServiceInfo>,
+    /// Open in the old scan only
+    pub closed: Vec<ServiceInfo>,
+    /// Open in both, but identified as a different service, product or version (old, new)
+    pub changed: Vec<(ServiceInfo, ServiceInfo)>,
+}
+
+impl ScanDiff {
+    pub fn is_empty(&self) -> bool {
+        self.opened.is_empty() && self.closed.is_empty() && self.changed.is_empty()
+    }
+}
+
+type PortKey = (String, Option<IpAddr>, Protocol, u16);
+
+fn key(info: &ServiceInfo) -> PortKey {
+    (info.host.clone(), info.ip, info.protocol.clone(), info.port)
+}
+
+fn open_ports(report: &ScanReport) -> HashMap<PortKey, &ServiceInfo> {
+    report.results.iter().filter(|r| r.state == PortState::Open).map(|r| (key(r), r)).collect()
+}
+
+/// The ports a scan probed, so a port outside another scan's range is not
+/// mistaken for one that closed
+struct Coverage<'a> {
+    report: &'a ScanReport,
+    tcp: HashSet<u16>,
+    udp: HashSet<u16>,
+    down: HashSet<IpAddr>,
+}
+
+impl<'a> Coverage<'a> {
+    fn new(report: &'a ScanReport) -> Self {
+        let ports = report.ports.as_ref();
+        Coverage {
+            report,
+            tcp: ports.map(|p| p.tcp.iter().copied().collect()).unwrap_or_default(),
+            udp: ports.map(|p| p.udp.iter().copied().collect()).unwrap_or_default(),
+            down: report.down_hosts().map(|status| status.ip).collect(),
+        }
+    }
+
+    fn covers(&self, info: &ServiceInfo) -> bool {
+        if info.ip.is_some_and(|ip| self.down.contains(&ip)) {
+            return false;
+        }
+        // Older reports do not record their ports, so count them as covering everything
+        if self.report.ports.is_none() {
+            return true;
+        }
+        let ports = match info.protocol {
+            Protocol::TCP => &self.tcp,
+            Protocol::UDP => &self.udp,
+        };
+        self.report.targets.iter().any(|target| {
+            target.host == info.host
+                && Some(target.ip) == info.ip
+                && match target.port {
+                    Some(port) => port == info.port && !ports.is_empty(),
+                    None => ports.contains(&info.port),
+                }
+        })
+    }
+}
+
+/// Compares the open ports of two scans, in the order they appear in each report.
+/// Only ports both scans probed count as opened or closed.
+pub fn diff(old: &ScanReport, new: &ScanReport) -> ScanDiff {
+    let (old_open, new_open) = (open_ports(old), open_ports(new));
+    let (old_coverage, new_coverage) = (Coverage::new(old), Coverage::new(new));
+
+    let mut diff = ScanDiff::default();
+    for info in new.results.iter().filter(|r| r.state == PortState::Open) {
+        match old_open.get(&key(info)) {
+            None if old_coverage.covers(info) => diff.opened.push(info.clone()),
+            None => {}
+            Some(before) if identity(before) != identity(info) => {
+                diff.changed.push(((*before).clone(), info.clone()));
+            }
+            Some(_) => {}
+        }
+    }
+    for info in old.results.iter().filter(|r| r.state == PortState::Open) {
+        if !new_open.contains_key(&key(info)) && new_coverage.covers(info) {
+            diff.closed.push(info.clone());
+        }
+    }
+    diff
+}
+
+/// What a port was identified as; a change in any part is reported
+fn identity(info: &ServiceInfo) -> (&Option<String>, &Option<String>, &Option<String>) {
+    (&info.service, &info.product, &info.version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(results: Vec<ServiceInfo>) -> ScanReport {
+        let target = ResolvedTarget { host: "localhost".to_string(), ip: "127.0.0.1".parse().unwrap(), port: None };
+        ScanReport {
+            addresses: vec![("localhost".to_string(), target.ip)],
+            targets: vec![target],
+            ports: Some(crate::ports::parse_port_spec("1-10000").unwrap()),
+            show_closed: false,
+            total_probes: 10,
+            completed_probes: 10,
+            cancelled: false,
+            results,
+            discovery: Vec::new(),
+        }
+    }
+
+    fn open(port: u16, service: &str) -> ServiceInfo {
+        ServiceInfo::new("localhost".to_string(), port, Protocol::TCP, PortState::Open)
+            .with_ip("127.0.0.1".parse().unwrap())
+            .with_service(service.to_string(), 0.9)
+    }
+
+    #[test]
+    fn test_diff_finds_opened_closed_and_changed_ports() {
+        let silent = ServiceInfo::new("localhost".to_string(), 53, Protocol::UDP, PortState::OpenFiltered);
+        let old = report(vec![open(22, "ssh"), open(80, "http"), open(8080, "http")]);
+        let new = report(vec![open(22, "ssh"), open(443, "https"), open(8080, "nginx"), silent]);
+
+        let diff = diff(&old, &new);
+        assert_eq!(diff.opened.iter().map(|r| r.port).collect::<Vec<_>>(), vec![443]);
+        assert_eq!(diff.closed.iter().map(|r| r.port).collect::<Vec<_>>(), vec![80]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].1.service.as_deref(), Some("nginx"));
+
+        assert!(super::diff(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_diff_ignores_ports_the_other_scan_did_not_probe() {
+        let old = report(vec![open(22, "ssh"), open(8080, "http")]);
+        let mut new = report(vec![open(22, "ssh"), open(60000, "http")]);
+        new.ports = Some(crate::ports::parse_port_spec("-").unwrap());
+        // 60000 was outside the old scan, and 8080 is still in range of the new one
+        let found = diff(&old, &new);
+        assert!(found.opened.is_empty());
+        assert_eq!(found.closed.iter().map(|r| r.port).collect::<Vec<_>>(), vec![8080]);
+
+        // A host found down was not probed at all
+        new.discovery.push(HostStatus {
+            host: "localhost".to_string(),
+            ip: "127.0.0.1".parse().unwrap(),
+            up: false,
+            reason: "no-response".to_string(),
+            latency_ms: None,
+        });
+        assert!(diff(&old, &new).closed.is_empty());
+
+        // Older reports do not record their ports and cover everything
+        let (mut old, mut new) = (old, report(new.results.clone()));
+        (old.ports, new.ports) = (None, None);
+        let found = diff(&old, &new);
+        assert_eq!(found.opened.iter().map(|r| r.port).collect::<Vec<_>>(), vec![60000]);
+        assert_eq!(found.closed.iter().map(|r| r.port).collect::<Vec<_>>(), vec![8080]);
+    }
+
+    #[test]
+    fn test_diff_counts_a_new_product_as_a_change() {
+        let old = report(vec![open(80, "HTTP").with_product("Caddy".to_string())]);
+        let new = report(vec![open(80, "HTTP").with_product("nginx".to_string())]);
+        let found = diff(&old, &new);
+        assert_eq!(found.changed.len(), 1);
+        assert_eq!(found.changed[0].1.display_service(), "HTTP (nginx)");
+    }
+
+    #[test]
+    fn test_report_round_trip() {
+        let path = std::env::temp_dir().join(format!("port-scanner-report-{}.json", std::process::id()));
+        report(vec![open(22, "ssh")]).save(&path).unwrap();
+        let loaded = ScanReport::load(&path);
+        let _ = fs::remove_file(&path);
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.hosts(), vec!["localhost".to_string()]);
+        assert_eq!(loaded.results[0].service.as_deref(), Some("ssh"));
+    }
+}
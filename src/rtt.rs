@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Smoothed round-trip time for one host, estimated the way TCP (RFC 6298) and nmap do:
+/// `timeout = srtt + 4 * rttvar`, clamped to the configured bounds
+#[derive(Debug, Clone, Copy)]
+pub struct RttEstimator {
+    srtt: Duration,
+    rttvar: Duration,
+    samples: u32,
+}
+
+impl RttEstimator {
+    fn new(sample: Duration) -> Self {
+        Self { srtt: sample, rttvar: sample / 2, samples: 1 }
+    }
+
+    fn record(&mut self, sample: Duration) {
+        // rttvar = 3/4 rttvar + 1/4 |srtt - sample|, srtt = 7/8 srtt + 1/8 sample
+        let delta = sample.abs_diff(self.srtt);
+        self.rttvar = (self.rttvar * 3 + delta) / 4;
+        self.srtt = (self.srtt * 7 + sample) / 8;
+        self.samples += 1;
+    }
+
+    fn timeout(&self) -> Duration {
+        self.srtt + self.rttvar * 4
+    }
+}
+
+/// Per-host RTT estimates shared by every probe of a scan.
+/// Hosts without a measurement yet use the initial timeout.
+#[derive(Debug)]
+pub struct RttTable {
+    initial: Duration,
+    min: Duration,
+    max: Duration,
+    hosts: Mutex<HashMap<IpAddr, RttEstimator>>,
+}
+
+impl RttTable {
+    pub fn new(initial_ms: u64, min_ms: u64, max_ms: u64) -> Self {
+        let min = Duration::from_millis(min_ms);
+        Self {
+            initial: Duration::from_millis(initial_ms),
+            min,
+            max: Duration::from_millis(max_ms).max(min),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records the time a connect took to be answered (SYN/ACK or RST)
+    pub fn record(&self, ip: IpAddr, sample: Duration) {
+        let mut hosts = self.hosts.lock().unwrap();
+        match hosts.get_mut(&ip) {
+            Some(estimator) => estimator.record(sample),
+            None => {
+                hosts.insert(ip, RttEstimator::new(sample));
+            }
+        }
+    }
+
+    /// The connect timeout to use for the next probe of `ip`
+    pub fn timeout(&self, ip: IpAddr) -> Duration {
+        match self.hosts.lock().unwrap().get(&ip) {
+            Some(estimator) => estimator.timeout().clamp(self.min, self.max),
+            None => self.initial,
+        }
+    }
+
+    pub fn estimate(&self, ip: IpAddr) -> Option<RttEstimator> {
+        self.hosts.lock().unwrap().get(&ip).copied()
+    }
+}
+
+impl std::fmt::Display for RttEstimator {
+    /// Formats the smoothed RTT with sub-millisecond precision, e.g. `rtt 0.42ms over 3 samples`
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "rtt {:.2}ms over {} samples", self.srtt.as_secs_f64() * 1000.0, self.samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeout_adapts_to_samples() {
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let table = RttTable::new(1000, 50, 3000);
+        assert_eq!(table.timeout(ip), Duration::from_millis(1000));
+
+        // A fast LAN host is clamped to the minimum
+        table.record(ip, Duration::from_millis(1));
+        assert_eq!(table.timeout(ip), Duration::from_millis(50));
+
+        // Steady 200ms replies settle around srtt + 4 * rttvar
+        let remote: IpAddr = "192.0.2.1".parse().unwrap();
+        for _ in 0..20 {
+            table.record(remote, Duration::from_millis(200));
+        }
+        let timeout = table.timeout(remote);
+        assert!(timeout >= Duration::from_millis(200) && timeout < Duration::from_millis(250), "{:?}", timeout);
+        assert_eq!(table.estimate(remote).unwrap().samples, 20);
+
+        // Huge samples are capped at the maximum
+        table.record(remote, Duration::from_secs(60));
+        assert_eq!(table.timeout(remote), Duration::from_millis(3000));
+    }
+}
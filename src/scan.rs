@@ -0,0 +1,590 @@
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::checkpoint::{Checkpoint, Checkpointer, CHECKPOINT_VERSION};
+use crate::connector::Connector;
+use crate::discovery::DiscoveryOptions;
+use crate::order::{random_seed, ScanOrder};
+use crate::ports::{parse_port_spec, PortSpec};
+use crate::proxy::Proxy;
+use crate::rtt::RttTable;
+use crate::scanner::{self, ScanHandles, ScanOptions};
+use crate::service_info::ServiceInfo;
+use crate::signatures::SignatureMatcher;
+use crate::sink::{ChannelSink, ResultSink, ScanEvent};
+use crate::syn::SynProber;
+use crate::targets::{parse_targets, resolve_targets, unique_addresses, unique_hosts, IpFamily, ResolvedTarget, TargetEntry, MAX_HOSTS};
+use crate::timing::{Timing, TimingTemplate};
+
+/// Signature database used when the builder is not given one
+pub const DEFAULT_SIGNATURES: &str = "signatures.json";
+
+/// Configures a scan: what to scan, how fast, and which signatures to fingerprint with.
+/// Start one with `Scanner::builder()`.
+#[derive(Default)]
+pub struct ScannerBuilder {
+    targets: Vec<TargetEntry>,
+    ip_family: IpFamily,
+    ports: Option<PortSpec>,
+    tcp: bool,
+    udp: bool,
+    timing: Timing,
+    max_per_host: Option<usize>,
+    show_closed: bool,
+    syn_scan: bool,
+    discovery: Option<DiscoveryOptions>,
+    skip_discovery: bool,
+    connector: Connector,
+    randomize: bool,
+    order: ScanOrder,
+    signatures: Option<Arc<SignatureMatcher>>,
+    signature_file: Option<String>,
+    checkpoint: Option<PathBuf>,
+    /// First invalid setting, reported by `build`
+    error: Option<String>,
+}
+
+impl ScannerBuilder {
+    /// Adds targets from a specification such as `10.0.0.0/30,example.com`
+    pub fn targets(mut self, spec: &str) -> Self {
+        match parse_targets(spec) {
+            Ok(hosts) => self.targets.extend(hosts.into_iter().map(TargetEntry::host)),
+            Err(e) => self.fail(format!("Invalid target: {}", e)),
+        }
+        self
+    }
+
+    /// Adds already parsed targets, e.g. from a target file
+    pub fn target_entries(mut self, entries: impl IntoIterator<Item = TargetEntry>) -> Self {
+        self.targets.extend(entries);
+        self
+    }
+
+    pub fn ip_family(mut self, family: IpFamily) -> Self {
+        self.ip_family = family;
+        self
+    }
+
+    pub fn ports(mut self, ports: PortSpec) -> Self {
+        self.ports = Some(ports);
+        self
+    }
+
+    /// Sets the ports from a specification such as `22,80,U:53`
+    pub fn port_spec(mut self, spec: &str) -> Self {
+        match parse_port_spec(spec) {
+            Ok(ports) => self.ports = Some(ports),
+            Err(e) => self.fail(format!("Invalid port list: {}", e)),
+        }
+        self
+    }
+
+    /// Restricts the scan to TCP and/or UDP; neither (the default) scans both
+    pub fn protocols(mut self, tcp: bool, udp: bool) -> Self {
+        self.tcp = tcp;
+        self.udp = udp;
+        self
+    }
+
+    pub fn timing(mut self, timing: Timing) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    pub fn timing_template(self, template: TimingTemplate) -> Self {
+        self.timing(template.timing())
+    }
+
+    pub fn max_per_host(mut self, max_per_host: Option<usize>) -> Self {
+        self.max_per_host = max_per_host;
+        self
+    }
+
+    /// Also report closed and filtered ports
+    pub fn show_closed(mut self, show_closed: bool) -> Self {
+        self.show_closed = show_closed;
+        self
+    }
+
+    /// Probes TCP ports with half-open SYN scans over raw sockets instead of full
+    /// connects. Needs Linux and root or CAP_NET_RAW; `build` fails without them.
+    /// Open ports are still fingerprinted over normal connections.
+    pub fn syn_scan(mut self, syn_scan: bool) -> Self {
+        self.syn_scan = syn_scan;
+        self
+    }
+
+    /// Pings hosts with these probes instead of `DiscoveryOptions::default()`
+    /// before scanning their ports
+    pub fn discovery(mut self, discovery: DiscoveryOptions) -> Self {
+        self.discovery = Some(discovery);
+        self
+    }
+
+    /// Scans the ports of every host, without first checking that it is up
+    pub fn skip_discovery(mut self, skip_discovery: bool) -> Self {
+        self.skip_discovery = skip_discovery;
+        self
+    }
+
+    /// Sends every probe from this local address instead of the one the route picks;
+    /// all targets must be of its address family
+    pub fn source_ip(mut self, source_ip: Option<IpAddr>) -> Self {
+        self.connector.source_ip = source_ip;
+        self
+    }
+
+    /// Binds every probe socket to this network device, e.g. a VLAN interface (Linux only)
+    pub fn interface(mut self, interface: Option<String>) -> Self {
+        self.connector.interface = interface;
+        self
+    }
+
+    /// Tunnels every TCP connection through a SOCKS5 or HTTP CONNECT proxy. Only TCP
+    /// connect scans work through a proxy: without an explicit protocol choice only
+    /// TCP ports are scanned, discovery only uses TCP pings, and `build` rejects UDP
+    /// ports and SYN scans.
+    pub fn proxy(mut self, proxy: Option<Proxy>) -> Self {
+        self.connector.proxy = proxy;
+        self
+    }
+
+    /// Probes the (host, port) pairs in a random order instead of target by target
+    /// with ascending ports. Without a `seed`, a random one is picked and kept in
+    /// `ScanOptions::order` so the order can be repeated.
+    pub fn randomize(mut self, randomize: bool) -> Self {
+        self.randomize = randomize;
+        self
+    }
+
+    /// Shuffles with this seed, repeating the order of an earlier scan of the same
+    /// targets and ports; implies `randomize`
+    pub fn seed(mut self, seed: Option<u64>) -> Self {
+        self.order.seed = seed;
+        self
+    }
+
+    /// Takes hosts in turn, one port each, so the load is spread across targets
+    pub fn interleave(mut self, interleave: bool) -> Self {
+        self.order.interleave = interleave;
+        self
+    }
+
+    /// Fingerprints with an already loaded signature database
+    pub fn signatures(mut self, matcher: Arc<SignatureMatcher>) -> Self {
+        self.signatures = Some(matcher);
+        self
+    }
+
+    /// Loads the signature database from this file instead of `signatures.json`
+    pub fn signature_file(mut self, path: &str) -> Self {
+        self.signature_file = Some(path.to_string());
+        self
+    }
+
+    /// Periodically saves progress to this file; see `Scanner::resume`
+    pub fn checkpoint(mut self, path: PathBuf) -> Self {
+        self.checkpoint = Some(path);
+        self
+    }
+
+    fn fail(&mut self, error: String) {
+        self.error.get_or_insert(error);
+    }
+
+    /// Validates the settings and resolves every target. DNS lookups block,
+    /// so async callers should run this on a blocking thread.
+    pub fn build(self) -> Result<Scanner, String> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let matcher = load_signatures(self.signatures, self.signature_file)?;
+
+        let proxied = self.connector.proxy.is_some();
+        let mut ports = self.ports.unwrap_or_else(|| PortSpec::range(1, 1024));
+        ports.select_protocols(self.tcp || (proxied && !self.udp), self.udp);
+        if ports.is_empty() {
+            return Err("No ports to scan for the selected protocol".to_string());
+        }
+        if proxied && !ports.udp.is_empty() {
+            return Err("UDP ports cannot be scanned through a proxy".to_string());
+        }
+        if proxied && self.syn_scan {
+            return Err("SYN scans cannot go through a proxy".to_string());
+        }
+        let mut discovery = self.discovery.unwrap_or_default();
+        if proxied {
+            discovery.udp_ports.clear();
+            discovery.icmp = false;
+        }
+
+        let mut targets = self.targets;
+        if targets.is_empty() {
+            targets.push(TargetEntry::host("127.0.0.1".to_string()));
+        }
+        if targets.len() > MAX_HOSTS {
+            return Err(format!("Targets expand to more than {} hosts", MAX_HOSTS));
+        }
+        let (resolved, dns_errors) = resolve_targets(&targets, self.ip_family);
+        if resolved.is_empty() {
+            return Err(format!("No targets could be resolved: {}", dns_errors.join("; ")));
+        }
+        self.connector.validate()?;
+        if let Some(target) = resolved.iter().find(|target| !self.connector.can_reach(target.ip)) {
+            let source = self.connector.source_ip.expect("only a source address limits the targets");
+            return Err(format!("Source address {} cannot reach {} ({})", source, target.host, target.ip));
+        }
+
+        let mut order = self.order;
+        if self.randomize {
+            order.seed.get_or_insert_with(random_seed);
+        }
+        let plan = Checkpoint {
+            version: CHECKPOINT_VERSION,
+            remaining: order.apply(scanner::build_work_items(&resolved, &ports)),
+            total_probes: scanner::count_probes(&resolved, &ports),
+            targets: resolved,
+            ports: Some(ports),
+            options: ScanOptions {
+                timing: self.timing,
+                max_per_host: self.max_per_host,
+                show_closed: self.show_closed,
+                syn_scan: self.syn_scan,
+                discovery: (!self.skip_discovery).then_some(discovery),
+                connector: self.connector,
+                order,
+            },
+            results: Vec::new(),
+        };
+        Scanner::new(plan, dns_errors, matcher, self.checkpoint)
+    }
+}
+
+fn load_signatures(matcher: Option<Arc<SignatureMatcher>>, path: Option<String>) -> Result<Arc<SignatureMatcher>, String> {
+    if let Some(matcher) = matcher {
+        return Ok(matcher);
+    }
+    let path = path.as_deref().unwrap_or(DEFAULT_SIGNATURES);
+    SignatureMatcher::load(path)
+        .map(Arc::new)
+        .map_err(|e| format!("Failed to load {}: {}", path, e))
+}
+
+/// A resolved, ready-to-run scan
+pub struct Scanner {
+    plan: Checkpoint,
+    dns_errors: Vec<String>,
+    matcher: Arc<SignatureMatcher>,
+    checkpoint: Option<PathBuf>,
+    sinks: Vec<Arc<dyn ResultSink>>,
+    rtt: Arc<RttTable>,
+    completed: Arc<AtomicUsize>,
+    cancel: CancellationToken,
+    syn: Option<Arc<SynProber>>,
+}
+
+impl Scanner {
+    pub fn builder() -> ScannerBuilder {
+        ScannerBuilder::default()
+    }
+
+    fn new(
+        plan: Checkpoint,
+        dns_errors: Vec<String>,
+        matcher: Arc<SignatureMatcher>,
+        checkpoint: Option<PathBuf>,
+    ) -> Result<Self, String> {
+        let syn = if plan.options.syn_scan {
+            let prober = SynProber::new(&plan.options.connector).map_err(|e| match e.kind() {
+                ErrorKind::PermissionDenied => format!("SYN scan needs root or CAP_NET_RAW: {}", e),
+                _ => format!("SYN scan unavailable: {}", e),
+            })?;
+            Some(Arc::new(prober))
+        } else {
+            None
+        };
+        Ok(Scanner {
+            rtt: Arc::new(plan.options.timing.rtt_table()),
+            completed: Arc::new(AtomicUsize::new(plan.completed_probes())),
+            plan,
+            dns_errors,
+            matcher,
+            checkpoint,
+            sinks: Vec::new(),
+            cancel: CancellationToken::new(),
+            syn,
+        })
+    }
+
+    /// Continues a scan from its checkpoint file, which keeps being updated.
+    /// Checkpoints do not keep proxy passwords, so a scan through an authenticating
+    /// proxy needs `proxy` again, naming the same proxy with its credentials.
+    pub fn resume(path: PathBuf, matcher: Arc<SignatureMatcher>, proxy: Option<Proxy>) -> Result<Self, String> {
+        let mut plan = Checkpoint::load(&path)?;
+        let connector = &mut plan.options.connector;
+        match (&connector.proxy, proxy) {
+            (Some(saved), Some(proxy)) if (saved.kind, saved.addr) == (proxy.kind, proxy.addr) => {
+                connector.proxy = Some(proxy);
+            }
+            (Some(saved), Some(proxy)) => {
+                return Err(format!("The scan went through proxy {}, not {}", saved, proxy));
+            }
+            (None, Some(_)) => return Err("The scan did not go through a proxy".to_string()),
+            (Some(saved), None) if saved.auth.is_some() => {
+                return Err(format!("Proxy {} needs its credentials again: pass them with --proxy", saved));
+            }
+            (_, None) => {}
+        }
+        if connector.proxy.is_some() {
+            connector.validate()?;
+        }
+        Scanner::new(plan, Vec::new(), matcher, Some(path))
+    }
+
+    /// Saves progress to `path` instead of the file the scan was configured with
+    pub fn with_checkpoint(mut self, path: PathBuf) -> Self {
+        self.checkpoint = Some(path);
+        self
+    }
+
+    /// Also reports every event of the scan to `sink`, as it happens
+    pub fn with_sink(mut self, sink: Arc<dyn ResultSink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    pub fn targets(&self) -> &[ResolvedTarget] {
+        &self.plan.targets
+    }
+
+    /// The ports probed on targets without a pinned port, if known
+    pub fn ports(&self) -> Option<&PortSpec> {
+        self.plan.ports.as_ref()
+    }
+
+    /// Distinct host names, in scan order
+    pub fn hosts(&self) -> Vec<String> {
+        unique_hosts(self.plan.targets.iter().map(|t| t.host.as_str()))
+    }
+
+    /// Distinct (host, address) pairs, in scan order
+    pub fn addresses(&self) -> Vec<(String, IpAddr)> {
+        unique_addresses(&self.plan.targets)
+    }
+
+    /// Hosts that failed to resolve and were skipped
+    pub fn dns_errors(&self) -> &[String] {
+        &self.dns_errors
+    }
+
+    pub fn options(&self) -> &ScanOptions {
+        &self.plan.options
+    }
+
+    /// Number of TCP and UDP probes in the whole scan
+    pub fn total_probes(&self) -> usize {
+        self.plan.total_probes
+    }
+
+    /// Probes already done by the run a resumed scan continues
+    pub fn completed_probes(&self) -> usize {
+        self.plan.completed_probes()
+    }
+
+    /// Results found by the run a resumed scan continues
+    pub fn resumed_results(&self) -> &[ServiceInfo] {
+        &self.plan.results
+    }
+
+    /// Shared counter of finished probes, including those of a resumed run
+    pub fn completed(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.completed)
+    }
+
+    /// Per-host round-trip times, filled in as the scan runs
+    pub fn rtt(&self) -> Arc<RttTable> {
+        Arc::clone(&self.rtt)
+    }
+
+    /// A token that cancels the scan once started
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Starts probing on the current tokio runtime
+    pub fn start(self) -> Scan {
+        let Scanner { plan, matcher, checkpoint, mut sinks, rtt, completed, cancel, syn, .. } = self;
+
+        let checkpointer = checkpoint.map(|path| Arc::new(Checkpointer::new(path, plan.clone())));
+        let writer = checkpointer.as_ref().map(|c| c.spawn_writer());
+
+        let (event_tx, events) = tokio::sync::mpsc::unbounded_channel();
+        sinks.push(Arc::new(ChannelSink(event_tx)));
+        let handles = ScanHandles {
+            matcher,
+            rtt: Arc::clone(&rtt),
+            completed: Arc::clone(&completed),
+            sink: Arc::new(sinks),
+            cancel: cancel.clone(),
+            checkpoint: checkpointer.clone(),
+            syn,
+        };
+        let engine = tokio::spawn(scanner::run(plan.remaining, plan.options, handles));
+
+        Scan {
+            events,
+            engine,
+            total_probes: plan.total_probes,
+            completed,
+            rtt,
+            cancel,
+            checkpointer,
+            writer,
+        }
+    }
+}
+
+/// A running scan. Results arrive through `next` as probes finish;
+/// `finish` waits for the engine and writes the final checkpoint.
+#[derive(Debug)]
+pub struct Scan {
+    events: UnboundedReceiver<ScanEvent>,
+    engine: JoinHandle<()>,
+    total_probes: usize,
+    completed: Arc<AtomicUsize>,
+    rtt: Arc<RttTable>,
+    cancel: CancellationToken,
+    checkpointer: Option<Arc<Checkpointer>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Scan {
+    /// The next result, or `None` once every probe is done or the scan was cancelled and drained
+    pub async fn next(&mut self) -> Option<ServiceInfo> {
+        while let Some(event) = self.events.recv().await {
+            if let ScanEvent::Service(info) = event {
+                return Some(info);
+            }
+        }
+        None
+    }
+
+    /// The next event of any kind, or `None` once the scan is over
+    pub async fn next_event(&mut self) -> Option<ScanEvent> {
+        self.events.recv().await
+    }
+
+    /// Stops sending new probes; probes in flight still report their results
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// A token that cancels this scan, for signal handlers and other tasks
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Shared counter of finished probes, including those of a resumed run
+    pub fn completed(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.completed)
+    }
+
+    /// (finished probes, total probes)
+    pub fn progress(&self) -> (usize, usize) {
+        (self.completed.load(Ordering::Relaxed), self.total_probes)
+    }
+
+    /// Per-host round-trip times measured so far
+    pub fn rtt(&self) -> Arc<RttTable> {
+        Arc::clone(&self.rtt)
+    }
+
+    /// Waits for the engine to stop and writes the final checkpoint, if checkpointing.
+    /// Events not read by then are dropped; sinks added with `Scanner::with_sink` still see them.
+    pub async fn finish(self) -> Result<(), String> {
+        drop(self.events);
+        let _ = self.engine.await;
+        if let Some(writer) = self.writer {
+            writer.abort();
+        }
+        match self.checkpointer {
+            Some(checkpointer) => checkpointer.save(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service_info::PortState;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_builder_reports_invalid_settings() {
+        let err = Scanner::builder().targets("10.0.0.1-").build().err().unwrap();
+        assert!(err.starts_with("Invalid target"), "{}", err);
+
+        let err = Scanner::builder().port_spec("99999").build().err().unwrap();
+        assert!(err.starts_with("Invalid port list"), "{}", err);
+
+        let err = Scanner::builder().port_spec("U:53").protocols(true, false).build().err().unwrap();
+        assert_eq!(err, "No ports to scan for the selected protocol");
+
+        let err = Scanner::builder().targets("10.0.0.0/16").targets("10.1.0.1").build().err().unwrap();
+        assert_eq!(err, format!("Targets expand to more than {} hosts", MAX_HOSTS));
+    }
+
+    #[test]
+    fn test_randomized_order_is_recorded_and_repeatable() {
+        let build = |builder: ScannerBuilder| builder.targets("10.0.0.1-2").port_spec("1-100").build().unwrap();
+        let ports = |scanner: &Scanner| scanner.plan.remaining.iter().map(|item| item.addr).collect::<Vec<_>>();
+
+        let sequential = build(Scanner::builder());
+        assert_eq!(sequential.options().order, ScanOrder::default());
+
+        let randomized = build(Scanner::builder().randomize(true));
+        let seed = randomized.options().order.seed.expect("a random seed is recorded");
+        assert_ne!(ports(&randomized), ports(&sequential));
+        let repeated = build(Scanner::builder().seed(Some(seed)));
+        assert_eq!(ports(&repeated), ports(&randomized));
+    }
+
+    #[tokio::test]
+    async fn test_scan_streams_results() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let scanner = Scanner::builder()
+            .targets("127.0.0.1")
+            .port_spec(&port.to_string())
+            .protocols(true, false)
+            .timing_template(TimingTemplate::Aggressive)
+            .build()
+            .unwrap();
+        assert_eq!(scanner.hosts(), vec!["127.0.0.1".to_string()]);
+        assert_eq!(scanner.total_probes(), 1);
+
+        let mut scan = scanner.start();
+        let mut results = Vec::new();
+        while let Some(info) = scan.next().await {
+            results.push(info);
+        }
+        assert_eq!(scan.progress(), (1, 1));
+        scan.finish().await.unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!((results[0].port, &results[0].state), (port, &PortState::Open));
+    }
+}
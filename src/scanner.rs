@@ -1,71 +1,874 @@
 use std::{
-    net::TcpStream,
-    sync::{Arc, Mutex, mpsc::{Receiver, Sender}},
+    sync::Arc,
     sync::atomic::{AtomicUsize, Ordering},
-    time::Duration,
+    time::{Duration, Instant},
 };
-use std::net::ToSocketAddrs;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
 
-use crate::udp::scan_udp;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+
+use crate::checkpoint::Checkpointer;
+use crate::connector::Connector;
+use crate::discovery::{discover_host, DiscoveryOptions};
+use crate::udp::{scan_udp, UdpResult};
 use crate::fingerprint::fingerprint_service;
-use crate::service_info::{ServiceInfo, Protocol};
+use crate::order::ScanOrder;
+use crate::ports::PortSpec;
+use crate::service_info::{ServiceInfo, PortState, Protocol};
 use crate::signatures::SignatureMatcher;
+use crate::sink::{ResultSink, ScanEvent};
+use crate::syn::SynProber;
+use crate::targets::ResolvedTarget;
+use crate::rate::{HostLimiter, RateLimiter};
+use crate::rtt::RttTable;
+use crate::timing::Timing;
+use crate::util::raise_fd_limit;
+
+
+/// A single unit of work for the scan workers: one port on one resolved host,
+/// probed over TCP, UDP or both
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkItem {
+    pub host: Arc<str>,
+    pub addr: SocketAddr,
+    pub tcp: bool,
+    pub udp: bool,
+}
+
+/// Builds the work items for every port on every target, target by target.
+/// Targets pinned to a port (`host:port`) only produce that one item, probed over
+/// every protocol that has ports in the spec.
+pub fn build_work_items(targets: &[ResolvedTarget], ports: &PortSpec) -> Vec<WorkItem> {
+    let tcp: HashSet<u16> = ports.tcp.iter().copied().collect();
+    let udp: HashSet<u16> = ports.udp.iter().copied().collect();
+    let all_ports = ports.all_ports();
+    let (pinned_tcp, pinned_udp) = (!ports.tcp.is_empty(), !ports.udp.is_empty());
+
+    let mut items = Vec::new();
+    for target in targets {
+        let host: Arc<str> = Arc::from(target.host.as_str());
+        match target.port {
+            Some(port) => items.push(WorkItem {
+                host,
+                addr: SocketAddr::new(target.ip, port),
+                tcp: pinned_tcp,
+                udp: pinned_udp,
+            }),
+            None => {
+                for &port in &all_ports {
+                    items.push(WorkItem {
+                        host: Arc::clone(&host),
+                        addr: SocketAddr::new(target.ip, port),
+                        tcp: tcp.contains(&port),
+                        udp: udp.contains(&port),
+                    });
+                }
+            }
+        }
+    }
+    items
+}
+
+/// Number of individual TCP and UDP probes `build_work_items` will produce
+pub fn count_probes(targets: &[ResolvedTarget], ports: &PortSpec) -> usize {
+    let pinned = usize::from(!ports.tcp.is_empty()) + usize::from(!ports.udp.is_empty());
+    targets
+        .iter()
+        .map(|target| match target.port {
+            Some(_) => pinned,
+            None => ports.tcp.len() + ports.udp.len(),
+        })
+        .sum()
+}
+
+/// Delay before the first retry of a timed-out probe
+const RETRY_BACKOFF_MS: u64 = 50;
+
+/// Settings shared by every probe of a scan. The default is a plain connect scan
+/// with the normal timing, reporting open ports only.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScanOptions {
+    /// Timeouts, retries and pacing, usually from a timing template
+    pub timing: Timing,
+    /// Maximum number of work items in flight against a single host
+    pub max_per_host: Option<usize>,
+    /// Report closed and filtered ports instead of only open ones
+    pub show_closed: bool,
+    /// Probe TCP ports with half-open SYN scans instead of full connects (Linux, CAP_NET_RAW)
+    #[serde(default)]
+    pub syn_scan: bool,
+    /// Pings every host first and skips the ports of hosts that do not answer;
+    /// `None` scans every host
+    #[serde(default)]
+    pub discovery: Option<DiscoveryOptions>,
+    /// Source address and interface every probe is sent from
+    #[serde(default)]
+    pub connector: Connector,
+    /// How the work items were ordered, with the seed of a randomized scan
+    #[serde(default)]
+    pub order: ScanOrder,
+}
+
+/// Outcome of a TCP connect probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpResult {
+    /// The handshake completed
+    Open,
+    /// The host answered with a RST (connection refused)
+    Closed,
+    /// Nothing came back before the timeout, typically a firewall dropping the SYN
+    Filtered,
+    /// The connect failed for another reason, e.g. an ICMP host/network unreachable
+    Unreachable(ErrorKind),
+}
+
+impl TcpResult {
+    pub fn state(self) -> PortState {
+        match self {
+            TcpResult::Open => PortState::Open,
+            TcpResult::Closed => PortState::Closed,
+            TcpResult::Filtered | TcpResult::Unreachable(_) => PortState::Filtered,
+        }
+    }
+
+    pub fn reason(self) -> String {
+        match self {
+            TcpResult::Open => "syn-ack".to_string(),
+            TcpResult::Closed => "conn-refused".to_string(),
+            TcpResult::Filtered => "no-response".to_string(),
+            TcpResult::Unreachable(kind) => format!("unreachable ({:?})", kind),
+        }
+    }
+}
+
+// Scans a TCP port on the given address and classifies the outcome.
+// Targets are resolved before the scan starts, so this never does DNS lookups.
+pub async fn scan_tcp(addr: SocketAddr, timeout_ms: u64, connector: &Connector) -> TcpResult {
+    match tokio::time::timeout(Duration::from_millis(timeout_ms), connector.connect(addr)).await {
+        Ok(Ok(_)) => TcpResult::Open,
+        Ok(Err(e)) => match e.kind() {
+            ErrorKind::ConnectionRefused => TcpResult::Closed,
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => TcpResult::Filtered,
+            kind => TcpResult::Unreachable(kind),
+        },
+        Err(_) => TcpResult::Filtered,
+    }
+}
 
+/// What a running scan shares with its caller: where it reports to, how it is
+/// stopped and the state the caller reads while it runs
+pub struct ScanHandles {
+    pub matcher: Arc<SignatureMatcher>,
+    /// Per-host round trips; TCP connect timeouts adapt to them as they are measured
+    pub rtt: Arc<RttTable>,
+    /// Incremented once per finished probe
+    pub completed: Arc<AtomicUsize>,
+    /// Receives every event as it happens
+    pub sink: Arc<dyn ResultSink>,
+    /// Stops new probes from being sent; probes already on the wire are drained
+    /// and reported
+    pub cancel: CancellationToken,
+    /// Records each finished work item by its index in the work items
+    pub checkpoint: Option<Arc<Checkpointer>>,
+    /// SYN scans TCP ports when given, instead of connecting
+    pub syn: Option<Arc<SynProber>>,
+}
 
-// Scans a TCP port on the given address. Returns `true` if the port is open.
-pub fn scan_tcp(addr: &str, port: u16, timeout_ms: u64) -> bool {
-    // Attempt to resolve the host:port into one or more SocketAddrs
-    let mut addrs_iter = match (addr, port).to_socket_addrs() {
-        Ok(iter) => iter,
-        Err(_)   => return false,  // can't resolve → treat as closed
+// Scans every work item with at most `options.timing.concurrency` items in flight
+// and reports every event to `handles.sink`, ending with `ScanEvent::ScanDone`.
+pub async fn run(work_items: Vec<WorkItem>, options: ScanOptions, handles: ScanHandles) {
+    let ScanHandles { matcher, rtt, completed, sink, cancel, checkpoint, syn } = handles;
+    // Every in-flight probe holds a socket
+    let concurrency = options.timing.concurrency.max(1);
+    raise_fd_limit(concurrency as u64 + 256);
+
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let per_host = options.max_per_host.map(HostLimiter::new);
+    let context = Arc::new(ScanContext {
+        rate: options.timing.max_rate.map(RateLimiter::new),
+        options,
+        matcher,
+        rtt,
+        completed,
+        sink,
+        cancel,
+        hosts_left: Mutex::new(HashMap::new()),
+        syn,
+    });
+
+    let down = match context.options.discovery {
+        Some(ref discovery) => discover(&work_items, discovery, &context, &semaphore).await,
+        None => HashSet::new(),
     };
+    {
+        let mut hosts_left = context.hosts_left.lock().unwrap();
+        for item in work_items.iter().filter(|item| !down.contains(&item.addr.ip())) {
+            *hosts_left.entry((Arc::clone(&item.host), item.addr.ip())).or_insert(0) += 1;
+        }
+    }
+
+    let mut items = Vec::with_capacity(work_items.len());
+    for (index, item) in work_items.into_iter().enumerate() {
+        if down.contains(&item.addr.ip()) {
+            context.completed.fetch_add(usize::from(item.tcp) + usize::from(item.udp), Ordering::Relaxed);
+            if let Some(ref checkpoint) = checkpoint {
+                checkpoint.record(index, Vec::new());
+            }
+        } else {
+            items.push((index, item));
+        }
+    }
 
-    // Try the first resolved address
-    if let Some(socket_addr) = addrs_iter.next() {
-        TcpStream::connect_timeout(&socket_addr, Duration::from_millis(timeout_ms))
-            .is_ok()
-    } else {
-        false
+    // Finished items hand their host's slot back through `freed`
+    let (freed_tx, mut freed_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut dispatcher = Dispatcher::new(items, per_host);
+    'dispatch: loop {
+        let permit = tokio::select! {
+            biased;
+            _ = context.cancel.cancelled() => break,
+            permit = Arc::clone(&semaphore).acquire_owned() => permit.expect("semaphore is never closed"),
+        };
+        let (index, item) = loop {
+            while let Ok(ip) = freed_rx.try_recv() {
+                dispatcher.release(ip);
+            }
+            match dispatcher.next() {
+                Dispatch::Item(index, item) => break (index, item),
+                Dispatch::Done => break 'dispatch,
+                Dispatch::Wait => tokio::select! {
+                    biased;
+                    _ = context.cancel.cancelled() => break 'dispatch,
+                    ip = freed_rx.recv() => dispatcher.release(ip.expect("the dispatcher holds a sender")),
+                },
+            }
+        };
+        let context = Arc::clone(&context);
+        let checkpoint = checkpoint.clone();
+        let freed_tx = freed_tx.clone();
+
+        tokio::spawn(async move {
+            let (host, ip) = (Arc::clone(&item.host), item.addr.ip());
+            if let Some(results) = probe_item(item, &context).await {
+                if let Some(checkpoint) = checkpoint {
+                    checkpoint.record(index, results);
+                }
+                context.item_done(host, ip);
+            }
+            drop(permit);
+            let _ = freed_tx.send(ip);
+        });
     }
+
+    // Once every permit is back, every probe has finished
+    let _ = semaphore.acquire_many(concurrency as u32).await;
+    context.sink.handle(&ScanEvent::ScanDone { cancelled: context.cancel.is_cancelled() });
 }
 
-// Worker loop: pulls port numbers from `task_rx`, scans TCP and UDP,
-// performs service fingerprinting, sends ServiceInfo results to `res_tx`,
-// and increments the shared `completed` counter.
-pub fn worker_loop(
-    task_rx: Arc<Mutex<Receiver<u16>>>,
-    res_tx: Sender<ServiceInfo>,
-    target: Arc<String>,
-    completed: Arc<AtomicUsize>,
-    timeout_ms: u64,
-    udp_timeout_ms: u64,
-    matcher: Arc<SignatureMatcher>,
-) {
-    loop {
-        let port = {
-            let rx_guard = task_rx.lock().unwrap();
-            match rx_guard.recv() {
-                Ok(p) => p,
-                Err(_) => break, // channel closed => exit loop
+/// What the dispatcher has to send next
+enum Dispatch {
+    Item(usize, WorkItem),
+    /// Every item left belongs to a host at its cap; wait for one to finish
+    Wait,
+    Done,
+}
+
+/// Hands out the work items in order, except that the items of a host already at
+/// `max_per_host` are held back until one of its items finishes. The items of
+/// other hosts keep going out meanwhile, so a capped host never stalls the scan.
+struct Dispatcher {
+    items: std::vec::IntoIter<(usize, WorkItem)>,
+    limiter: Option<HostLimiter>,
+    /// Held back items per address, in order
+    parked: HashMap<IpAddr, VecDeque<(usize, WorkItem)>>,
+    parked_count: usize,
+    /// Held back items whose host got a slot back, which go out first
+    ready: VecDeque<(usize, WorkItem)>,
+}
+
+impl Dispatcher {
+    fn new(items: Vec<(usize, WorkItem)>, limiter: Option<HostLimiter>) -> Self {
+        Dispatcher {
+            items: items.into_iter(),
+            limiter,
+            parked: HashMap::new(),
+            parked_count: 0,
+            ready: VecDeque::new(),
+        }
+    }
+
+    fn next(&mut self) -> Dispatch {
+        if let Some((index, item)) = self.ready.pop_front() {
+            return Dispatch::Item(index, item);
+        }
+        for (index, item) in self.items.by_ref() {
+            let ip = item.addr.ip();
+            if self.limiter.as_mut().is_none_or(|limiter| limiter.try_acquire(ip)) {
+                return Dispatch::Item(index, item);
             }
+            self.parked.entry(ip).or_default().push_back((index, item));
+            self.parked_count += 1;
+        }
+        if self.parked_count == 0 {
+            Dispatch::Done
+        } else {
+            Dispatch::Wait
+        }
+    }
+
+    /// Gives back the slot of a finished item of `ip`, passing it straight on to
+    /// the host's next held back item
+    fn release(&mut self, ip: IpAddr) {
+        let Some(ref mut limiter) = self.limiter else { return };
+        limiter.release(ip);
+        if let Some(next) = self.parked.get_mut(&ip).and_then(VecDeque::pop_front) {
+            limiter.try_acquire(ip);
+            self.parked_count -= 1;
+            self.ready.push_back(next);
+        }
+    }
+}
+
+/// Pings every distinct address of the work items, reports each as a
+/// `ScanEvent::Discovery` and returns the addresses that are down. Each host holds
+/// one concurrency permit and one rate limit slot per ping. Hosts not pinged
+/// before a cancel are not reported and count as up.
+async fn discover(
+    work_items: &[WorkItem],
+    discovery: &DiscoveryOptions,
+    context: &Arc<ScanContext>,
+    semaphore: &Arc<Semaphore>,
+) -> HashSet<IpAddr> {
+    let permits = discovery.ping_count().clamp(1, context.options.timing.concurrency.max(1)) as u32;
+    let pings_per_host = discovery.ping_count() as u32;
+    let mut seen = HashSet::new();
+    let mut pings = tokio::task::JoinSet::new();
+    for item in work_items {
+        let ip = item.addr.ip();
+        if !seen.insert(ip) {
+            continue;
+        }
+        let permit = tokio::select! {
+            biased;
+            _ = context.cancel.cancelled() => break,
+            permit = Arc::clone(semaphore).acquire_many_owned(permits) => permit.expect("semaphore is never closed"),
         };
+        let (context, discovery, host) = (Arc::clone(context), discovery.clone(), Arc::clone(&item.host));
+        pings.spawn(async move {
+            if !context.throttle_n(pings_per_host).await {
+                return None;
+            }
+            let options = &context.options;
+            let status = discover_host(&host, ip, &discovery, &options.timing, &options.connector).await;
+            drop(permit);
+            if let Some(latency) = status.latency_ms {
+                context.rtt.record(ip, Duration::from_secs_f64(latency / 1000.0));
+            }
+            context.sink.handle(&ScanEvent::Discovery(status.clone()));
+            Some(status)
+        });
+    }
 
-        // Scan TCP
-        if scan_tcp(&target, port, timeout_ms) {
-            // Perform fingerprinting
-            let service_info = fingerprint_service(&target, port, Protocol::TCP, &matcher);
-            println!("{}", service_info.display_full());
-            let _ = res_tx.send(service_info);
+    let mut down = HashSet::new();
+    while let Some(status) = pings.join_next().await {
+        if let Ok(Some(status)) = status {
+            if !status.up {
+                down.insert(status.ip);
+            }
         }
+    }
+    down
+}
 
-        // Scan UDP
-        if scan_udp(&target, port, udp_timeout_ms) {
-            let service_info = fingerprint_service(&target, port, Protocol::UDP, &matcher);
-            println!("{}", service_info.display_full());
-            let _ = res_tx.send(service_info);
+/// State shared by every probe task of one scan
+struct ScanContext {
+    options: ScanOptions,
+    matcher: Arc<SignatureMatcher>,
+    rtt: Arc<RttTable>,
+    rate: Option<RateLimiter>,
+    completed: Arc<AtomicUsize>,
+    sink: Arc<dyn ResultSink>,
+    cancel: CancellationToken,
+    /// Work items not yet finished per (host, address)
+    hosts_left: Mutex<HashMap<(Arc<str>, IpAddr), usize>>,
+    syn: Option<Arc<SynProber>>,
+}
+
+impl ScanContext {
+    /// Waits for the global rate limit, if any, before sending a probe.
+    /// Returns false if the scan was cancelled, in which case the probe must not be sent.
+    async fn throttle(&self) -> bool {
+        self.throttle_n(1).await
+    }
+
+    /// `throttle` for `n` probes sent together, such as the pings of one host
+    async fn throttle_n(&self, n: u32) -> bool {
+        if let Some(ref rate) = self.rate {
+            tokio::select! {
+                biased;
+                _ = self.cancel.cancelled() => return false,
+                _ = rate.acquire_n(n) => {}
+            }
         }
+        !self.cancel.is_cancelled()
+    }
 
-        // Update progress
+    /// Counts a finished work item, reporting the host once its last item is done
+    fn item_done(&self, host: Arc<str>, ip: IpAddr) {
+        let mut hosts_left = self.hosts_left.lock().unwrap();
+        let key = (host, ip);
+        if let Some(left) = hosts_left.get_mut(&key) {
+            *left -= 1;
+            if *left == 0 {
+                hosts_left.remove(&key);
+                drop(hosts_left);
+                let (host, ip) = key;
+                self.sink.handle(&ScanEvent::HostDone { host, ip });
+            }
+        }
+    }
+}
+
+/// Probes one work item over TCP and/or UDP and reports the results.
+/// Returns everything reported, or `None` if cancellation cut the item short.
+async fn probe_item(item: WorkItem, context: &ScanContext) -> Option<Vec<ServiceInfo>> {
+    let ScanContext { options, matcher, completed, sink, .. } = context;
+    let WorkItem { host, addr, tcp, udp } = item;
+    let mut reported = Vec::new();
+    let mut report = |service_info: ServiceInfo| {
+        reported.push(service_info.clone());
+        sink.handle(&ScanEvent::Service(service_info));
+    };
+    let port_open = |protocol| sink.handle(&ScanEvent::PortOpen { host: Arc::clone(&host), addr, protocol });
+
+    // Scan TCP
+    if tcp {
+        let (result, sample, retries) = probe_tcp(addr, context).await?;
+        match result {
+            TcpResult::Open => {
+                port_open(Protocol::TCP);
+                let mut service_info = fingerprint(&host, addr, Protocol::TCP, None, options, matcher).await
+                    .with_retries(retries);
+                if let Some(sample) = sample {
+                    service_info = service_info.with_rtt(sample);
+                }
+                report(service_info);
+            }
+            result if options.show_closed => {
+                let mut service_info = ServiceInfo::new(host.to_string(), addr.port(), Protocol::TCP, result.state())
+                    .with_ip(addr.ip())
+                    .with_reason(result.reason())
+                    .with_retries(retries);
+                if let Some(sample) = sample {
+                    service_info = service_info.with_rtt(sample);
+                }
+                report(service_info);
+            }
+            _ => {}
+        }
         completed.fetch_add(1, Ordering::Relaxed);
     }
+
+    // Scan UDP
+    if udp {
+        let (result, retries) = probe_udp(addr, context).await?;
+        match result {
+            UdpResult::Open(reply) => {
+                port_open(Protocol::UDP);
+                let service_info = fingerprint(&host, addr, Protocol::UDP, Some(reply), options, matcher).await
+                    .with_retries(retries);
+                report(service_info);
+            }
+            // Open|filtered is always reported so summaries can count it
+            result if options.show_closed || result == UdpResult::OpenFiltered => {
+                let service_info = ServiceInfo::new(host.to_string(), addr.port(), Protocol::UDP, result.state())
+                    .with_ip(addr.ip())
+                    .with_reason(result.reason())
+                    .with_retries(retries);
+                report(service_info);
+            }
+            _ => {}
+        }
+        completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    Some(reported)
+}
+
+/// Connects to a TCP port, resending after a backoff while the connect times out.
+/// Returns the result, the measured round trip (for a SYN/ACK or RST) and the retry count,
+/// or `None` if the scan was cancelled before the first connect.
+async fn probe_tcp(addr: SocketAddr, context: &ScanContext) -> Option<(TcpResult, Option<Duration>, u32)> {
+    if !context.throttle().await {
+        return None;
+    }
+    let mut retries = 0;
+    loop {
+        let timeout = context.rtt.timeout(addr.ip());
+        let started = Instant::now();
+        let result = match context.syn {
+            Some(ref syn) if syn.supports(addr) => syn.probe(addr, timeout).await,
+            _ => scan_tcp(addr, timeout.as_millis() as u64, &context.options.connector).await,
+        };
+
+        // Both a SYN/ACK and a RST measure the round trip; timeouts do not
+        if matches!(result, TcpResult::Open | TcpResult::Closed) {
+            let sample = started.elapsed();
+            context.rtt.record(addr.ip(), sample);
+            return Some((result, Some(sample), retries));
+        }
+
+        // Only silence is worth retrying; an ICMP error is a definite answer
+        // A cancelled scan keeps the answer it already has instead of retrying
+        if result != TcpResult::Filtered || retries >= context.options.timing.retries || context.cancel.is_cancelled() {
+            return Some((result, None, retries));
+        }
+        tokio::time::sleep(retry_backoff(retries + 1)).await;
+        if !context.throttle().await {
+            return Some((result, None, retries));
+        }
+        retries += 1;
+    }
+}
+
+/// Sends a UDP probe, resending after a backoff while no reply or ICMP error comes back.
+/// Returns `None` if the scan was cancelled before the first datagram was sent.
+async fn probe_udp(addr: SocketAddr, context: &ScanContext) -> Option<(UdpResult, u32)> {
+    if !context.throttle().await {
+        return None;
+    }
+    let mut retries = 0;
+    loop {
+        let result = scan_udp(addr, context.options.timing.udp_timeout_ms, &context.options.connector).await;
+        if result != UdpResult::OpenFiltered || retries >= context.options.timing.retries || context.cancel.is_cancelled() {
+            return Some((result, retries));
+        }
+        tokio::time::sleep(retry_backoff(retries + 1)).await;
+        if !context.throttle().await {
+            return Some((result, retries));
+        }
+        retries += 1;
+    }
+}
+
+/// Delay before the given retry: doubles from `RETRY_BACKOFF_MS` on each attempt
+fn retry_backoff(retry: u32) -> Duration {
+    Duration::from_millis(RETRY_BACKOFF_MS << retry.saturating_sub(1).min(6))
+}
+
+/// Runs the blocking fingerprinting probes on tokio's blocking pool
+async fn fingerprint(
+    host: &Arc<str>,
+    addr: SocketAddr,
+    protocol: Protocol,
+    udp_reply: Option<Vec<u8>>,
+    options: &ScanOptions,
+    matcher: &Arc<SignatureMatcher>,
+) -> ServiceInfo {
+    let (host, matcher) = (Arc::clone(host), Arc::clone(matcher));
+    let (timing, connector) = (options.timing, options.connector.clone());
+    let fallback = ServiceInfo::new(host.to_string(), addr.port(), protocol.clone(), PortState::Open)
+        .with_ip(addr.ip());
+
+    tokio::task::spawn_blocking(move || {
+        fingerprint_service(&host, addr, protocol, udp_reply.as_deref(), &matcher, &timing, &connector)
+    })
+    .await
+    .unwrap_or(fallback)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::ChannelSink;
+    use std::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_scan_tcp_open_and_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert_eq!(scan_tcp(addr, 500, &Connector::default()).await, TcpResult::Open);
+
+        drop(listener);
+        let result = scan_tcp(addr, 500, &Connector::default()).await;
+        assert_eq!(result, TcpResult::Closed);
+        assert_eq!(result.state(), PortState::Closed);
+        assert_eq!(result.reason(), "conn-refused");
+    }
+
+    #[test]
+    fn test_tcp_result_states() {
+        assert_eq!(TcpResult::Filtered.state(), PortState::Filtered);
+        let unreachable = TcpResult::Unreachable(ErrorKind::HostUnreachable);
+        assert_eq!(unreachable.state(), PortState::Filtered);
+        assert_eq!(unreachable.reason(), "unreachable (HostUnreachable)");
+    }
+
+    #[test]
+    fn test_build_work_items_counts_probes() {
+        let target = |port| ResolvedTarget {
+            host: "localhost".to_string(),
+            ip: "127.0.0.1".parse().unwrap(),
+            port,
+        };
+        let targets = vec![target(None), target(Some(8080))];
+
+        let ports = PortSpec { tcp: vec![22, 80], udp: vec![53] };
+        let items = build_work_items(&targets, &ports);
+        assert_eq!(items.len(), 4);
+        let probes = items.iter().map(|i| usize::from(i.tcp) + usize::from(i.udp)).sum::<usize>();
+        assert_eq!(probes, 5);
+        assert_eq!(count_probes(&targets, &ports), 5);
+
+        // A TCP-only scan probes pinned ports over TCP only
+        let ports = PortSpec { tcp: vec![22, 80], udp: vec![] };
+        let items = build_work_items(&targets, &ports);
+        assert!(items.iter().all(|i| i.tcp && !i.udp));
+        assert_eq!(count_probes(&targets, &ports), 3);
+    }
+
+    #[test]
+    fn test_retry_backoff_doubles() {
+        assert_eq!(retry_backoff(1), Duration::from_millis(50));
+        assert_eq!(retry_backoff(2), Duration::from_millis(100));
+        assert_eq!(retry_backoff(3), Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_only_timeouts_are_retried() {
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let closed = {
+            let s = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            s.local_addr().unwrap()
+        };
+
+        let options = ScanOptions {
+            timing: Timing {
+                timeout_ms: 500,
+                max_timeout_ms: 1000,
+                udp_timeout_ms: 50,
+                concurrency: 10,
+                retries: 2,
+                ..Timing::default()
+            },
+            show_closed: true,
+            ..ScanOptions::default()
+        };
+        let context = ScanContext {
+            rtt: Arc::new(options.timing.rtt_table()),
+            rate: None,
+            options,
+            matcher: Arc::new(SignatureMatcher::load("signatures.json").unwrap()),
+            completed: Arc::new(AtomicUsize::new(0)),
+            sink: Arc::new(Vec::new()),
+            cancel: CancellationToken::new(),
+            hosts_left: Mutex::new(HashMap::new()),
+            syn: None,
+        };
+
+        let result = probe_udp(silent.local_addr().unwrap(), &context).await;
+        assert_eq!(result, Some((UdpResult::OpenFiltered, 2)));
+
+        // A port-unreachable is definite and is not resent
+        let result = probe_udp(closed, &context).await;
+        assert_eq!(result, Some((UdpResult::Closed, 0)));
+    }
+
+    #[tokio::test]
+    async fn test_scan_tcp_ipv6_loopback() {
+        // Skip on hosts without IPv6 loopback
+        let Ok(listener) = TcpListener::bind("[::1]:0") else { return };
+        let open = listener.local_addr().unwrap();
+        assert_eq!(scan_tcp(open, 500, &Connector::default()).await, TcpResult::Open);
+
+        drop(listener);
+        assert_eq!(scan_tcp(open, 500, &Connector::default()).await, TcpResult::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_run_reports_every_probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        let closed = {
+            let l = TcpListener::bind("127.0.0.1:0").unwrap();
+            l.local_addr().unwrap()
+        };
+
+        let host: Arc<str> = Arc::from("127.0.0.1");
+        let items = [open, closed]
+            .into_iter()
+            .map(|addr| WorkItem { host: Arc::clone(&host), addr, tcp: true, udp: false })
+            .collect();
+        let options = ScanOptions {
+            timing: Timing {
+                timeout_ms: 500,
+                max_timeout_ms: 1000,
+                concurrency: 2,
+                max_rate: Some(1000),
+                retries: 0,
+                ..Timing::default()
+            },
+            max_per_host: Some(1),
+            show_closed: true,
+            ..ScanOptions::default()
+        };
+        let (events, completed, rtt) = run_items(items, options, CancellationToken::new()).await;
+
+        // The open port is announced before its service, and the host and scan end last
+        assert!(matches!(&events[0], ScanEvent::PortOpen { addr, .. } if *addr == open));
+        assert!(matches!(&events[events.len() - 2], ScanEvent::HostDone { ip, .. } if *ip == open.ip()));
+        assert!(matches!(events.last(), Some(ScanEvent::ScanDone { cancelled: false })));
+
+        let mut results = services(events);
+        results.sort_by_key(|r| r.state != PortState::Open);
+        assert_eq!(completed, 2);
+        assert_eq!(results.len(), 2);
+        assert_eq!((results[0].port, &results[0].state), (open.port(), &PortState::Open));
+        assert_eq!((results[1].port, &results[1].state), (closed.port(), &PortState::Closed));
+
+        // Both the SYN/ACK and the RST were timed
+        assert!(results.iter().all(|r| r.rtt_ms.is_some()));
+        assert!(rtt.estimate(open.ip()).unwrap().to_string().ends_with("over 2 samples"));
+    }
+
+    #[tokio::test]
+    async fn test_host_cap_does_not_stall_other_hosts() {
+        // Silent UDP ports hold each probe for the whole receive timeout
+        let silent = ["127.0.0.1:0", "127.0.0.2:0"].map(|addr| std::net::UdpSocket::bind(addr).unwrap());
+        let items = silent
+            .iter()
+            .flat_map(|socket| {
+                let addr = socket.local_addr().unwrap();
+                let host: Arc<str> = Arc::from(addr.ip().to_string());
+                std::iter::repeat_n(addr, 3).map(move |addr| WorkItem { host: Arc::clone(&host), addr, tcp: false, udp: true })
+            })
+            .collect();
+        let options = ScanOptions {
+            timing: Timing { udp_timeout_ms: 200, concurrency: 10, retries: 0, ..Timing::default() },
+            max_per_host: Some(1),
+            show_closed: true,
+            ..ScanOptions::default()
+        };
+        let (events, completed, _) = run_items(items, options, CancellationToken::new()).await;
+
+        assert_eq!(completed, 6);
+        // One probe per host is in flight at a time, so the first of each host
+        // finish together, before either host's second probe
+        let finished: Vec<IpAddr> = services(events).iter().map(|r| r.ip.unwrap()).collect();
+        assert_eq!(finished.len(), 6);
+        assert_ne!(finished[0], finished[1], "{:?}", finished);
+        assert_ne!(finished[2], finished[3], "{:?}", finished);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_drains_in_flight_probes() {
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let host: Arc<str> = Arc::from("127.0.0.1");
+        let items = std::iter::repeat_n(silent.local_addr().unwrap(), 20)
+            .map(|addr| WorkItem { host: Arc::clone(&host), addr, tcp: false, udp: true })
+            .collect();
+        let options = ScanOptions {
+            timing: Timing { udp_timeout_ms: 200, concurrency: 1, retries: 5, ..Timing::default() },
+            show_closed: true,
+            ..ScanOptions::default()
+        };
+        let cancel = CancellationToken::new();
+        let scan = tokio::spawn(run_items(items, options, cancel.clone()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let started = Instant::now();
+        cancel.cancel();
+        let (events, completed, _) = scan.await.unwrap();
+
+        // The first probe finishes its current attempt and is reported; nothing else is sent
+        assert!(started.elapsed() < Duration::from_millis(500), "{:?}", started.elapsed());
+        assert_eq!(completed, 1);
+        // The host was cut short, so it is never reported done
+        assert!(matches!(events.last(), Some(ScanEvent::ScanDone { cancelled: true })));
+        assert!(!events.iter().any(|e| matches!(e, ScanEvent::HostDone { .. })));
+        let results = services(events);
+        assert_eq!(results.len(), 1);
+        assert_eq!((&results[0].state, results[0].retries), (&PortState::OpenFiltered, 0));
+    }
+
+    #[tokio::test]
+    async fn test_down_hosts_are_not_scanned() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        let host: Arc<str> = Arc::from("127.0.0.1");
+        let items = vec![
+            WorkItem { host: Arc::clone(&host), addr: open, tcp: true, udp: true },
+            WorkItem { host: Arc::clone(&host), addr: SocketAddr::new(open.ip(), 1), tcp: true, udp: false },
+        ];
+        // Without any pings nothing can answer, so the host is down
+        let options = ScanOptions {
+            show_closed: true,
+            discovery: Some(DiscoveryOptions { tcp_ports: vec![], udp_ports: vec![], icmp: false }),
+            ..ScanOptions::default()
+        };
+        let (events, completed, _) = run_items(items, options, CancellationToken::new()).await;
+
+        assert_eq!(completed, 3);
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], ScanEvent::Discovery(status) if !status.up && status.reason == "no-response"));
+        assert!(matches!(events[1], ScanEvent::ScanDone { cancelled: false }));
+    }
+
+    #[tokio::test]
+    async fn test_discovery_pings_count_against_the_rate() {
+        let hosts = ["127.0.0.1", "127.0.0.2", "127.0.0.3"];
+        let items = hosts
+            .iter()
+            .map(|host| WorkItem { host: Arc::from(*host), addr: SocketAddr::new(host.parse().unwrap(), 1), tcp: true, udp: false })
+            .collect();
+        // Two pings per host and one probe each: 9 slots, 50ms apart
+        let options = ScanOptions {
+            timing: Timing { max_rate: Some(20), retries: 0, ..Timing::default() },
+            discovery: Some(DiscoveryOptions { tcp_ports: vec![1, 2], udp_ports: vec![], icmp: false }),
+            ..ScanOptions::default()
+        };
+        let started = Instant::now();
+        let (events, completed, _) = run_items(items, options, CancellationToken::new()).await;
+
+        // Counting each host's pings as one probe would finish after 5 intervals
+        assert!(started.elapsed() >= Duration::from_millis(400), "{:?}", started.elapsed());
+        assert_eq!(completed, 3);
+        assert_eq!(events.iter().filter(|e| matches!(e, ScanEvent::Discovery(status) if status.up)).count(), 3);
+    }
+
+    /// Runs a scan of `items` and collects every event it reports, with the
+    /// number of finished probes and the measured round trips
+    async fn run_items(items: Vec<WorkItem>, options: ScanOptions, cancel: CancellationToken) -> (Vec<ScanEvent>, usize, Arc<RttTable>) {
+        let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel();
+        let handles = ScanHandles {
+            matcher: Arc::new(SignatureMatcher::load("signatures.json").unwrap()),
+            rtt: Arc::new(options.timing.rtt_table()),
+            completed: Arc::new(AtomicUsize::new(0)),
+            sink: Arc::new(ChannelSink(event_tx)),
+            cancel,
+            checkpoint: None,
+            syn: None,
+        };
+        let (completed, rtt) = (Arc::clone(&handles.completed), Arc::clone(&handles.rtt));
+        run(items, options, handles).await;
+
+        let mut events = Vec::new();
+        while let Some(event) = event_rx.recv().await {
+            events.push(event);
+        }
+        (events, completed.load(Ordering::Relaxed), rtt)
+    }
+
+    fn services(events: Vec<ScanEvent>) -> Vec<ServiceInfo> {
+        events
+            .into_iter()
+            .filter_map(|event| match event {
+                ScanEvent::Service(info) => Some(info),
+                _ => None,
+            })
+            .collect()
+    }
 }
@@ -1,7 +1,10 @@
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Protocol {
     TCP,
     UDP,
@@ -19,14 +22,19 @@ impl fmt::Display for Protocol {
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PortState {
     Open,
+    Closed,
     Filtered,
+    /// No reply to a UDP probe: either open and silent, or filtered
+    OpenFiltered,
 }
 
 impl fmt::Display for PortState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             PortState::Open => write!(f, "OPEN"),
+            PortState::Closed => write!(f, "CLOSED"),
             PortState::Filtered => write!(f, "FILTERED"),
+            PortState::OpenFiltered => write!(f, "OPEN|FILTERED"),
         }
     }
 }
@@ -40,36 +48,68 @@ pub struct TlsInfo {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceInfo {
+    pub host: String,
+    #[serde(default)]
+    pub ip: Option<IpAddr>,
     pub port: u16,
     pub protocol: Protocol,
     pub state: PortState,
     pub service: Option<String>,
+    /// The software the service identified itself as, such as the SSH software
+    /// string or the HTTP `Server` header
+    #[serde(default)]
+    pub product: Option<String>,
     pub version: Option<String>,
     pub banner: Option<String>,
     pub tls_info: Option<TlsInfo>,
     pub confidence: f32,
+    /// Why the port was classified the way it was (e.g. `conn-refused`, `no-response`)
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Time the connect took to be answered, in milliseconds
+    #[serde(default)]
+    pub rtt_ms: Option<f64>,
+    /// How many times the probe was resent after timing out
+    #[serde(default)]
+    pub retries: u32,
 }
 
 impl ServiceInfo {
-    pub fn new(port: u16, protocol: Protocol, state: PortState) -> Self {
+    pub fn new(host: String, port: u16, protocol: Protocol, state: PortState) -> Self {
         Self {
+            host,
+            ip: None,
             port,
             protocol,
             state,
             service: None,
+            product: None,
             version: None,
             banner: None,
             tls_info: None,
             confidence: 0.0,
+            reason: None,
+            rtt_ms: None,
+            retries: 0,
         }
     }
 
+    pub fn with_ip(mut self, ip: IpAddr) -> Self {
+        self.ip = Some(ip);
+        self
+    }
+
     pub fn with_service(mut self, service: String, confidence: f32) -> Self {
         self.service = Some(service);
         self.confidence = confidence;
         self
     }
 
+    pub fn with_product(mut self, product: String) -> Self {
+        self.product = Some(product);
+        self
+    }
+
     pub fn with_version(mut self, version: String) -> Self {
         self.version = Some(version);
         self
@@ -80,6 +120,21 @@ impl ServiceInfo {
         self
     }
 
+    pub fn with_reason(mut self, reason: String) -> Self {
+        self.reason = Some(reason);
+        self
+    }
+
+    pub fn with_rtt(mut self, rtt: Duration) -> Self {
+        self.rtt_ms = Some(rtt.as_secs_f64() * 1000.0);
+        self
+    }
+
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
     pub fn with_tls_info(mut self, tls_info: TlsInfo) -> Self {
         self.tls_info = Some(tls_info);
         self
@@ -94,6 +149,12 @@ impl ServiceInfo {
             parts.push("unknown".to_string());
         }
 
+        if let Some(ref product) = self.product {
+            if self.service.as_ref() != Some(product) {
+                parts.push(format!("({})", product));
+            }
+        }
+
         if let Some(ref version) = self.version {
             parts.push(format!("v{}", version));
         }
@@ -101,20 +162,33 @@ impl ServiceInfo {
         parts.join(" ")
     }
 
-    pub fn display_full(&self) -> String {
-        let mut output = format!(
-            "{} Port {} ({}) - {}",
-            match self.protocol {
-                Protocol::TCP => "TCP",
-                Protocol::UDP => "UDP",
+    /// Formats the scanned endpoint, showing the resolved address next to hostnames
+    pub fn endpoint(&self) -> String {
+        match self.ip {
+            Some(ip) if self.host == ip.to_string() => SocketAddr::new(ip, self.port).to_string(),
+            Some(ip) => format!("{} {}", self.host, SocketAddr::new(ip, self.port)),
+            None => match self.host.parse::<IpAddr>() {
+                Ok(ip) => SocketAddr::new(ip, self.port).to_string(),
+                Err(_) => format!("{}:{}", self.host, self.port),
             },
-            self.port,
-            match self.state {
-                PortState::Open => "OPEN",
-                PortState::Filtered => "FILTERED",
-            },
-            self.display_service()
-        );
+        }
+    }
+
+    pub fn display_full(&self) -> String {
+        let mut output = format!("{} {} ({})", self.protocol, self.endpoint(), self.state);
+
+        // Closed and filtered ports are not fingerprinted, so only name a service when there is one
+        if self.state == PortState::Open || self.service.is_some() {
+            output.push_str(&format!(" - {}", self.display_service()));
+        }
+
+        if let Some(ref reason) = self.reason {
+            output.push_str(&format!(" | Reason: {}", reason));
+        }
+
+        if self.retries > 0 {
+            output.push_str(&format!(" | Retries: {}", self.retries));
+        }
 
         if let Some(ref banner) = self.banner {
             output.push_str(&format!(" | Banner: {}", banner));
@@ -131,3 +205,61 @@ impl ServiceInfo {
         output
     }
 }
+
+/// Groups results by scanned address, keeping hosts in scan order and sorting each
+/// group by port. A dual-stack host scanned over both families gets one group per
+/// address, labelled `host (address)`.
+pub fn group_by_host<'a>(addresses: &[(String, IpAddr)], results: &'a [ServiceInfo]) -> Vec<(String, Vec<&'a ServiceInfo>)> {
+    addresses
+        .iter()
+        .map(|(host, ip)| {
+            let mut host_results: Vec<&ServiceInfo> = results
+                .iter()
+                .filter(|r| &r.host == host && r.ip == Some(*ip))
+                .collect();
+            host_results.sort_by_key(|r| (r.port, r.protocol.to_string()));
+
+            let label = if *host == ip.to_string() {
+                host.clone()
+            } else {
+                format!("{} ({})", host, ip)
+            };
+            (label, host_results)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_brackets_ipv6() {
+        let info = ServiceInfo::new("::1".to_string(), 22, Protocol::TCP, PortState::Open);
+        assert_eq!(info.endpoint(), "[::1]:22");
+        assert_eq!(info.clone().with_ip("::1".parse().unwrap()).endpoint(), "[::1]:22");
+
+        let info = ServiceInfo::new("example.com".to_string(), 443, Protocol::TCP, PortState::Open)
+            .with_ip("2001:db8::1".parse().unwrap());
+        assert_eq!(info.endpoint(), "example.com [2001:db8::1]:443");
+        assert!(info.display_full().starts_with("TCP example.com [2001:db8::1]:443 (OPEN)"));
+    }
+
+    #[test]
+    fn test_group_by_host_separates_families() {
+        let v4: IpAddr = "192.0.2.1".parse().unwrap();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+        let results = vec![
+            ServiceInfo::new("dual.example".to_string(), 443, Protocol::TCP, PortState::Open).with_ip(v6),
+            ServiceInfo::new("dual.example".to_string(), 80, Protocol::TCP, PortState::Open).with_ip(v4),
+            ServiceInfo::new("dual.example".to_string(), 22, Protocol::TCP, PortState::Open).with_ip(v4),
+        ];
+        let addresses = vec![("dual.example".to_string(), v4), ("dual.example".to_string(), v6)];
+
+        let groups = group_by_host(&addresses, &results);
+        assert_eq!(groups[0].0, "dual.example (192.0.2.1)");
+        assert_eq!(groups[0].1.iter().map(|r| r.port).collect::<Vec<_>>(), vec![22, 80]);
+        assert_eq!(groups[1].0, "dual.example (2001:db8::1)");
+        assert_eq!(groups[1].1.len(), 1);
+    }
+}
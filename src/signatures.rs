@@ -3,6 +3,8 @@ use regex::Regex;
 use std::collections::HashMap;
 use std::fs;
 
+use crate::ports::PortSpec;
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct BannerPattern {
     pub pattern: String,
@@ -25,6 +27,50 @@ pub struct SignatureDatabase {
     pub banner_patterns: Vec<BannerPattern>,
     pub http_server_patterns: Vec<HttpServerPattern>,
     pub port_hints: HashMap<String, String>,
+    /// TCP ports ordered from most to least commonly open
+    #[serde(default)]
+    pub top_tcp_ports: Vec<u16>,
+    /// UDP ports ordered from most to least commonly open
+    #[serde(default)]
+    pub top_udp_ports: Vec<u16>,
+}
+
+impl SignatureDatabase {
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Lists every entry that is loaded but can never match or is out of range.
+    /// Patterns that fail to compile are skipped silently when loading a matcher.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut check = |kind: &str, index: usize, pattern: &str, confidence: f32, version_group: Option<usize>| {
+            let name = format!("{} pattern {} ({:?})", kind, index, pattern);
+            match Regex::new(pattern) {
+                Ok(regex) => {
+                    if let Some(group) = version_group.filter(|&g| g >= regex.captures_len()) {
+                        problems.push(format!("{}: version_group {} but the pattern has no such group", name, group));
+                    }
+                }
+                Err(e) => problems.push(format!("{}: {}", name, e)),
+            }
+            if !(0.0..=1.0).contains(&confidence) {
+                problems.push(format!("{}: confidence {} is not between 0 and 1", name, confidence));
+            }
+        };
+        for (i, p) in self.banner_patterns.iter().enumerate() {
+            check("banner", i, &p.pattern, p.confidence, None);
+        }
+        for (i, p) in self.http_server_patterns.iter().enumerate() {
+            check("http_server", i, &p.pattern, p.confidence, p.version_group);
+        }
+
+        let mut hints: Vec<&String> = self.port_hints.keys().filter(|k| k.parse::<u16>().is_err()).collect();
+        hints.sort();
+        problems.extend(hints.into_iter().map(|k| format!("port hint {:?}: not a port number", k)));
+        problems
+    }
 }
 
 pub struct SignatureMatcher {
@@ -43,8 +89,7 @@ pub struct Match {
 
 impl SignatureMatcher {
     pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let content = fs::read_to_string(path)?;
-        let database: SignatureDatabase = serde_json::from_str(&content)?;
+        let database = SignatureDatabase::load(path)?;
 
         // Compile banner regex patterns
         let mut banner_regexes = Vec::new();
@@ -103,12 +148,25 @@ impl SignatureMatcher {
         None
     }
 
+    pub fn database(&self) -> &SignatureDatabase {
+        &self.database
+    }
+
     pub fn get_port_hint(&self, port: u16) -> Option<String> {
         self.database
             .port_hints
             .get(&port.to_string())
             .cloned()
     }
+
+    /// Returns the `n` most common ports for each protocol, or every ranked port of
+    /// a protocol whose table is shorter than `n`
+    pub fn top_ports(&self, n: usize) -> PortSpec {
+        PortSpec {
+            tcp: self.database.top_tcp_ports.iter().take(n).copied().collect(),
+            udp: self.database.top_udp_ports.iter().take(n).copied().collect(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -139,4 +197,33 @@ mod tests {
         assert_eq!(m.product, "nginx");
         assert_eq!(m.version, Some("1.18.0".to_string()));
     }
+
+    #[test]
+    fn test_database_problems() {
+        let database = SignatureDatabase::load("signatures.json").unwrap();
+        assert_eq!(database.problems(), Vec::<String>::new());
+
+        let broken: SignatureDatabase = serde_json::from_str(r#"{
+            "banner_patterns": [{ "pattern": "^SSH-(", "service": "ssh", "product": "SSH", "confidence": 0.9 }],
+            "http_server_patterns": [{ "pattern": "nginx", "service": "http", "product": "nginx", "confidence": 1.5, "version_group": 1 }],
+            "port_hints": { "22": "ssh", "http": "http" }
+        }"#).unwrap();
+        let problems = broken.problems();
+        assert_eq!(problems.len(), 4, "{:#?}", problems);
+        assert!(problems[0].starts_with("banner pattern 0"));
+        assert!(problems[1].contains("version_group 1"));
+        assert!(problems[2].contains("confidence 1.5"));
+        assert_eq!(problems[3], "port hint \"http\": not a port number");
+    }
+
+    #[test]
+    fn test_top_ports() {
+        let matcher = SignatureMatcher::load("signatures.json").unwrap();
+
+        let top = matcher.top_ports(3);
+        assert_eq!(top.tcp, vec![80, 23, 443]);
+        assert_eq!(top.udp, vec![631, 161, 137]);
+        let all = matcher.top_ports(1000);
+        assert_eq!((all.tcp.len(), all.udp.len()), (100, 50));
+    }
 }
@@ -0,0 +1,157 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Stdout, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::discovery::HostStatus;
+use crate::service_info::{PortState, Protocol, ServiceInfo};
+
+/// Something that happened during a scan, in the order the engine saw it
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ScanEvent {
+    /// Host discovery finished for one address, before any of its ports are probed
+    Discovery(HostStatus),
+    /// A port answered; fingerprinting it starts now
+    PortOpen { host: Arc<str>, addr: SocketAddr, protocol: Protocol },
+    /// The final result for one port: the identified service of an open port, or a
+    /// closed, filtered or open|filtered port when those are reported
+    Service(ServiceInfo),
+    /// Every port of this address has been probed
+    HostDone { host: Arc<str>, ip: IpAddr },
+    /// The engine stopped, either after the last probe or after draining a cancel
+    ScanDone { cancelled: bool },
+}
+
+/// Receives the events of a running scan. Called from the probe tasks as results
+/// arrive, so implementations should not block for long.
+pub trait ResultSink: Send + Sync {
+    fn handle(&self, event: &ScanEvent);
+}
+
+/// Passes every event on to each sink in turn
+impl ResultSink for Vec<Arc<dyn ResultSink>> {
+    fn handle(&self, event: &ScanEvent) {
+        for sink in self {
+            sink.handle(event);
+        }
+    }
+}
+
+/// Forwards events to a channel; events sent after the receiver is gone are dropped
+pub struct ChannelSink(pub UnboundedSender<ScanEvent>);
+
+impl ResultSink for ChannelSink {
+    fn handle(&self, event: &ScanEvent) {
+        let _ = self.0.send(event.clone());
+    }
+}
+
+/// Prints open ports the way the CLI shows them, one line each as they are identified
+pub struct PrettySink<W: Write + Send> {
+    out: Mutex<W>,
+}
+
+impl PrettySink<Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl PrettySink<BufWriter<File>> {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write + Send> PrettySink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out: Mutex::new(out) }
+    }
+}
+
+impl<W: Write + Send> ResultSink for PrettySink<W> {
+    fn handle(&self, event: &ScanEvent) {
+        let mut out = self.out.lock().unwrap();
+        let written = match event {
+            ScanEvent::Service(info) if info.state == PortState::Open => writeln!(out, "{}", info.display_full()),
+            ScanEvent::ScanDone { .. } => out.flush(),
+            _ => Ok(()),
+        };
+        if let Err(e) = written {
+            eprintln!("Warning: failed to write result: {}", e);
+        }
+    }
+}
+
+/// Writes every event as one JSON object per line, for other tools to consume
+pub struct JsonLinesSink<W: Write + Send> {
+    out: Mutex<W>,
+}
+
+impl JsonLinesSink<BufWriter<File>> {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write + Send> JsonLinesSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out: Mutex::new(out) }
+    }
+}
+
+impl<W: Write + Send> ResultSink for JsonLinesSink<W> {
+    fn handle(&self, event: &ScanEvent) {
+        let mut out = self.out.lock().unwrap();
+        let mut written = serde_json::to_writer(&mut *out, event)
+            .map_err(io::Error::from)
+            .and_then(|()| writeln!(out));
+        if let ScanEvent::ScanDone { .. } = event {
+            written = written.and_then(|()| out.flush());
+        }
+        if let Err(e) = written {
+            eprintln!("Warning: failed to write event: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sinks_format_events() {
+        let open = ServiceInfo::new("localhost".to_string(), 22, Protocol::TCP, PortState::Open);
+        let closed = ServiceInfo::new("localhost".to_string(), 23, Protocol::TCP, PortState::Closed);
+        let events = [
+            ScanEvent::PortOpen { host: Arc::from("localhost"), addr: "127.0.0.1:22".parse().unwrap(), protocol: Protocol::TCP },
+            ScanEvent::Service(open.clone()),
+            ScanEvent::Service(closed),
+            ScanEvent::ScanDone { cancelled: false },
+        ];
+
+        let pretty = PrettySink::new(Vec::new());
+        let json = JsonLinesSink::new(Vec::new());
+        for event in &events {
+            pretty.handle(event);
+            json.handle(event);
+        }
+
+        // Only the open port is printed
+        let pretty = String::from_utf8(pretty.out.into_inner().unwrap()).unwrap();
+        assert_eq!(pretty, format!("{}\n", open.display_full()));
+
+        let json = String::from_utf8(json.out.into_inner().unwrap()).unwrap();
+        let lines: Vec<serde_json::Value> = json.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["event"], "port_open");
+        assert_eq!(lines[0]["addr"], "127.0.0.1:22");
+        assert_eq!((&lines[1]["event"], &lines[1]["port"]), (&"service".into(), &22.into()));
+        assert_eq!(lines[3], serde_json::json!({ "event": "scan_done", "cancelled": false }));
+    }
+}
@@ -0,0 +1,94 @@
+use port_scanner::report::{ScanDiff, ScanReport};
+use port_scanner::rtt::RttTable;
+use port_scanner::service_info::group_by_host;
+use port_scanner::{PortState, ServiceInfo};
+
+fn count_state<'a>(results: impl Iterator<Item = &'a ServiceInfo>, state: PortState) -> usize {
+    results.filter(|r| r.state == state).count()
+}
+
+/// Prints the totals and the per-host results of a scan. `rtt` adds the
+/// measured round-trip estimates of a scan that just ran.
+pub fn print_summary(report: &ScanReport, rtt: Option<&RttTable>) {
+    let results = &report.results;
+    let show_closed = report.show_closed;
+
+    println!("\n========== SCAN SUMMARY ==========");
+    if report.cancelled {
+        println!(
+            "Scan cancelled after {}/{} probes; results are partial",
+            report.completed_probes, report.total_probes
+        );
+    }
+    println!("Hosts scanned: {}", report.hosts().len());
+    if !report.discovery.is_empty() {
+        let down = report.down_hosts().count();
+        println!("Hosts up: {}, down: {}", report.discovery.len() - down, down);
+    }
+    println!("Total open ports found: {}", count_state(results.iter(), PortState::Open));
+    if show_closed {
+        println!("Closed ports: {}", count_state(results.iter(), PortState::Closed));
+        println!("Filtered ports: {}", count_state(results.iter(), PortState::Filtered));
+    }
+    println!("Open|filtered UDP ports: {}", count_state(results.iter(), PortState::OpenFiltered));
+    println!("==================================");
+
+    for (host, host_results) in group_by_host(&report.addresses, results) {
+        if host_results.is_empty() {
+            continue;
+        }
+        let count = |state| count_state(host_results.iter().copied(), state);
+        let open_filtered = count(PortState::OpenFiltered);
+        let mut counts = format!("{} open", count(PortState::Open));
+        if show_closed {
+            counts.push_str(&format!(", {} closed, {} filtered", count(PortState::Closed), count(PortState::Filtered)));
+        }
+        if open_filtered > 0 {
+            counts.push_str(&format!(", {} open|filtered", open_filtered));
+        }
+        if let Some(estimate) = rtt.and_then(|rtt| host_results[0].ip.and_then(|ip| rtt.estimate(ip))) {
+            counts.push_str(&format!(", {}", estimate));
+        }
+        println!("\n{} ({})", host, counts);
+
+        // Silent UDP ports are not conclusive, so only list them on request
+        for info in &host_results {
+            if info.state != PortState::OpenFiltered || show_closed {
+                println!("[RESULT] {}", info.display_full());
+            }
+        }
+        if open_filtered > 0 && !show_closed {
+            println!("[UDP] {} open|filtered ports not shown (use --show-closed to list them)", open_filtered);
+        }
+    }
+
+    if report.down_hosts().next().is_some() {
+        println!("\nHosts down, not port scanned (use --skip-discovery to scan them anyway):");
+        for status in report.down_hosts() {
+            println!("[DOWN] {} ({}): {}", status.host, status.ip, status.reason);
+        }
+    }
+}
+
+/// Prints the open ports that changed between two scans, one line each
+pub fn print_diff(diff: &ScanDiff) {
+    if diff.is_empty() {
+        println!("No changes in open ports");
+        return;
+    }
+    for info in &diff.opened {
+        println!("+ {}", info.display_full());
+    }
+    for info in &diff.closed {
+        println!("- {}", info.display_full());
+    }
+    for (old, new) in &diff.changed {
+        println!("~ {} {}: {} -> {}", old.protocol, old.endpoint(), old.display_service(), new.display_service());
+    }
+    println!(
+        "\n{} opened, {} closed, {} changed",
+        diff.opened.len(),
+        diff.closed.len(),
+        diff.changed.len()
+    );
+}
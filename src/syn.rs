@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::oneshot;
+
+use crate::connector::Connector;
+use crate::scanner::TcpResult;
+use crate::util::internet_checksum;
+
+/// Linux's default `ip_local_port_range`, assumed when it cannot be read
+const DEFAULT_EPHEMERAL_PORTS: (u16, u16) = (32768, 60999);
+
+/// Fewest source ports worth taking from above the ephemeral range; with fewer the
+/// ports below it are used instead
+const MIN_SOURCE_PORTS: usize = 1024;
+
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+/// Replies are matched on the probed address and the source port the SYN came from
+type ProbeKey = (SocketAddr, u16);
+
+/// Probes waiting for a reply, with the sequence number their SYN carried
+type Pending = Mutex<HashMap<ProbeKey, (u32, oneshot::Sender<TcpResult>)>>;
+
+/// Half-open TCP scanning: sends a bare SYN over a raw socket and classifies the port
+/// from the reply without completing the handshake, so no connection ever reaches the
+/// service and no socket is held per probe. The kernel answers the SYN/ACK with a RST
+/// on its own. Needs Linux and root or CAP_NET_RAW; ICMP errors are not captured, so
+/// unreachable ports show up as filtered.
+pub struct SynProber {
+    v4: Arc<sys::RawSocket>,
+    /// `None` when the host has no IPv6 support; IPv6 targets then fall back to connect scans
+    v6: Option<Arc<sys::RawSocket>>,
+    pending: Arc<Pending>,
+    stop: Arc<AtomicBool>,
+    /// Source ports used for SYN probes; every probe in flight gets its own, so replies
+    /// can be matched to the probe that caused them
+    source_ports: RangeInclusive<u16>,
+    /// Index into `source_ports` of the next probe's port
+    next_port: AtomicUsize,
+    seq_base: u32,
+    /// Local address the kernel routes each probed address from, for the TCP checksum
+    sources: Mutex<HashMap<IpAddr, IpAddr>>,
+    connector: Connector,
+}
+
+impl SynProber {
+    /// Opens the raw sockets, bound to `connector`'s source address and interface,
+    /// and starts the threads reading replies from them
+    pub fn new(connector: &Connector) -> io::Result<Self> {
+        let source_ports = source_ports()?;
+        let v4 = Arc::new(sys::RawSocket::open(false, connector)?);
+        let v6 = sys::RawSocket::open(true, connector).ok().map(Arc::new);
+        let pending = Arc::new(Pending::default());
+        let stop = Arc::new(AtomicBool::new(false));
+
+        for socket in std::iter::once(&v4).chain(v6.as_ref()) {
+            let (socket, pending, stop) = (Arc::clone(socket), Arc::clone(&pending), Arc::clone(&stop));
+            thread::spawn(move || receive_replies(&socket, &pending, &stop));
+        }
+
+        let clock = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Ok(SynProber {
+            v4,
+            v6,
+            pending,
+            stop,
+            next_port: AtomicUsize::new(clock.subsec_micros() as usize % source_ports.len()),
+            source_ports,
+            seq_base: clock.subsec_nanos() ^ std::process::id().rotate_left(16),
+            sources: Mutex::new(HashMap::new()),
+            connector: connector.clone(),
+        })
+    }
+
+    /// True if `addr` can be SYN scanned; other addresses need a connect scan
+    pub fn supports(&self, addr: SocketAddr) -> bool {
+        addr.is_ipv4() || self.v6.is_some()
+    }
+
+    /// Sends one SYN and waits up to `timeout` for the SYN/ACK (open) or RST (closed)
+    pub async fn probe(&self, addr: SocketAddr, timeout: Duration) -> TcpResult {
+        let socket = match (addr, &self.v6) {
+            (SocketAddr::V4(_), _) => &self.v4,
+            (SocketAddr::V6(_), Some(v6)) => v6,
+            (SocketAddr::V6(_), None) => return TcpResult::Unreachable(io::ErrorKind::Unsupported),
+        };
+        let source = match self.source_for(addr.ip()) {
+            Ok(source) => source,
+            Err(e) => return TcpResult::Unreachable(e.kind()),
+        };
+
+        let port = self.source_port();
+        let seq = self.seq_base.wrapping_add(u32::from(port).wrapping_mul(0x9e37_79b9));
+        let key = (addr, port);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(key, (seq, tx));
+
+        let segment = build_syn(source, addr, port, seq);
+        if let Err(e) = socket.send_to(&segment, addr.ip()) {
+            self.pending.lock().unwrap().remove(&key);
+            return TcpResult::Unreachable(e.kind());
+        }
+
+        let reply = tokio::time::timeout(timeout, rx).await;
+        self.pending.lock().unwrap().remove(&key);
+        match reply {
+            Ok(Ok(result)) => result,
+            _ => TcpResult::Filtered,
+        }
+    }
+
+    fn source_port(&self) -> u16 {
+        let count = self.source_ports.len();
+        let index = self
+            .next_port
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |index| Some((index + 1) % count))
+            .unwrap_or_default();
+        self.source_ports.start() + index as u16
+    }
+
+    /// Asks the kernel which local address it would send to `ip` from, without sending anything
+    fn source_for(&self, ip: IpAddr) -> io::Result<IpAddr> {
+        if let Some(&source) = self.sources.lock().unwrap().get(&ip) {
+            return Ok(source);
+        }
+        let addr = SocketAddr::new(ip, 9);
+        let socket = self.connector.udp_socket(addr)?;
+        socket.connect(addr)?;
+        let source = socket.local_addr()?.ip();
+        self.sources.lock().unwrap().insert(ip, source);
+        Ok(source)
+    }
+}
+
+impl Drop for SynProber {
+    fn drop(&mut self) {
+        // The reader threads notice within one receive timeout and close their sockets
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Source ports outside the kernel's ephemeral port range, so SYN/ACKs never land on
+/// the kernel's own connections and no connection the kernel opens meanwhile takes
+/// the port of a probe in flight
+fn source_ports() -> io::Result<RangeInclusive<u16>> {
+    let (low, high) = fs::read_to_string("/proc/sys/net/ipv4/ip_local_port_range")
+        .ok()
+        .and_then(|text| parse_port_range(&text))
+        .unwrap_or(DEFAULT_EPHEMERAL_PORTS);
+    outside_range(low, high).ok_or_else(|| {
+        io::Error::other(format!("no source ports left outside the ephemeral port range {}-{}", low, high))
+    })
+}
+
+/// Parses `ip_local_port_range`: the first and last ephemeral port, separated by whitespace
+fn parse_port_range(text: &str) -> Option<(u16, u16)> {
+    let mut ports = text.split_whitespace().map(|port| port.parse::<u16>().ok());
+    match (ports.next()??, ports.next()??) {
+        (low, high) if low <= high => Some((low, high)),
+        _ => None,
+    }
+}
+
+/// The ports above the ephemeral range if there are enough of them, else the
+/// unprivileged ports below it
+fn outside_range(low: u16, high: u16) -> Option<RangeInclusive<u16>> {
+    let above = high.checked_add(1).map(|start| start..=u16::MAX);
+    let below = (low > 1024).then(|| 1024..=low - 1);
+    match (above, below) {
+        (Some(above), Some(below)) if above.len() < MIN_SOURCE_PORTS && below.len() > above.len() => Some(below),
+        (Some(above), _) => Some(above),
+        (None, below) => below,
+    }
+}
+
+/// Reads every TCP segment arriving on `socket` and completes the probe it answers
+fn receive_replies(socket: &sys::RawSocket, pending: &Pending, stop: &AtomicBool) {
+    let mut buf = [0u8; 1500];
+    while !stop.load(Ordering::Relaxed) {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(_) => continue,
+        };
+        let Some(reply) = parse_reply(&buf[..len], from) else { continue };
+
+        let mut pending = pending.lock().unwrap();
+        let key = (reply.from, reply.to_port);
+        // A reply to our SYN acknowledges its sequence number; anything else is unrelated traffic
+        if matches!(pending.get(&key), Some((seq, _)) if reply.ack == seq.wrapping_add(1)) {
+            if let Some((_, tx)) = pending.remove(&key) {
+                let _ = tx.send(reply.result);
+            }
+        }
+    }
+}
+
+/// A SYN/ACK or RST addressed to one of our source ports
+#[derive(Debug, PartialEq, Eq)]
+struct Reply {
+    from: SocketAddr,
+    to_port: u16,
+    ack: u32,
+    result: TcpResult,
+}
+
+/// Parses a received packet: a whole IPv4 packet, or a bare TCP segment from an IPv6
+/// raw socket (which strips the IP header). Returns `None` for anything but a SYN/ACK or RST.
+fn parse_reply(packet: &[u8], from: IpAddr) -> Option<Reply> {
+    let segment = match from {
+        IpAddr::V4(_) => {
+            let header_len = usize::from(packet.first()? & 0x0f) * 4;
+            if packet.first()? >> 4 != 4 || packet.get(9) != Some(&6) {
+                return None;
+            }
+            packet.get(header_len..)?
+        }
+        IpAddr::V6(_) => packet,
+    };
+    if segment.len() < 20 {
+        return None;
+    }
+
+    let word = |at: usize| u16::from_be_bytes([segment[at], segment[at + 1]]);
+    let flags = segment[13];
+    let result = if flags & (TCP_SYN | TCP_ACK) == TCP_SYN | TCP_ACK {
+        TcpResult::Open
+    } else if flags & TCP_RST != 0 {
+        TcpResult::Closed
+    } else {
+        return None;
+    };
+    Some(Reply {
+        from: SocketAddr::new(from, word(0)),
+        to_port: word(2),
+        ack: u32::from_be_bytes([segment[8], segment[9], segment[10], segment[11]]),
+        result,
+    })
+}
+
+/// Builds a TCP SYN segment (header plus an MSS option) with its checksum filled in
+fn build_syn(source: IpAddr, dest: SocketAddr, source_port: u16, seq: u32) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(24);
+    segment.extend_from_slice(&source_port.to_be_bytes());
+    segment.extend_from_slice(&dest.port().to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&0u32.to_be_bytes()); // ack
+    segment.push(6 << 4); // data offset: 6 words, including the option
+    segment.push(TCP_SYN);
+    segment.extend_from_slice(&1024u16.to_be_bytes()); // window
+    segment.extend_from_slice(&[0, 0]); // checksum, filled in below
+    segment.extend_from_slice(&[0, 0]); // urgent pointer
+    segment.extend_from_slice(&[2, 4, 0x05, 0xb4]); // MSS 1460
+
+    let checksum = tcp_checksum(source, dest.ip(), &segment);
+    segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+    segment
+}
+
+/// The TCP checksum over the IPv4 or IPv6 pseudo header and the segment
+fn tcp_checksum(source: IpAddr, dest: IpAddr, segment: &[u8]) -> u16 {
+    let mut pseudo = Vec::with_capacity(40);
+    match (source, dest) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => {
+            pseudo.extend_from_slice(&s.octets());
+            pseudo.extend_from_slice(&d.octets());
+            pseudo.extend_from_slice(&[0, 6]);
+            pseudo.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+        }
+        (s, d) => {
+            let v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            pseudo.extend_from_slice(&v6(s).octets());
+            pseudo.extend_from_slice(&v6(d).octets());
+            pseudo.extend_from_slice(&(segment.len() as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, 6]);
+        }
+    }
+    internet_checksum(&[&pseudo, segment])
+}
+
+/// Raw IPPROTO_TCP sockets: sends TCP segments (the kernel adds the IP header) and
+/// receives every TCP packet addressed to this host
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+    use std::mem;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    use socket2::SockRef;
+
+    use crate::connector::Connector;
+    use crate::util::sockaddr;
+
+    pub struct RawSocket(OwnedFd);
+
+    impl RawSocket {
+        pub fn open(v6: bool, connector: &Connector) -> io::Result<Self> {
+            let domain = if v6 { libc::AF_INET6 } else { libc::AF_INET };
+            let fd = unsafe { libc::socket(domain, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::IPPROTO_TCP) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let socket = RawSocket(unsafe { OwnedFd::from_raw_fd(fd) });
+
+            // Wake up regularly so the reader thread can notice the prober was dropped
+            let timeout = libc::timeval { tv_sec: 0, tv_usec: 200_000 };
+            socket.set_option(libc::SO_RCVTIMEO, &timeout)?;
+            // Fast scans get many replies at once; a larger buffer keeps them from being dropped
+            socket.set_option(libc::SO_RCVBUF, &(4 << 20 as libc::c_int))?;
+            connector.bind_socket(SockRef::from(&socket.0), v6)?;
+            Ok(socket)
+        }
+
+        fn set_option<T>(&self, name: libc::c_int, value: &T) -> io::Result<()> {
+            let ret = unsafe {
+                libc::setsockopt(
+                    self.0.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    name,
+                    value as *const T as *const libc::c_void,
+                    mem::size_of::<T>() as libc::socklen_t,
+                )
+            };
+            if ret == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+        }
+
+        pub fn send_to(&self, segment: &[u8], dest: IpAddr) -> io::Result<()> {
+            // Raw sockets take no port: it must stay 0
+            let (storage, len) = sockaddr(SocketAddr::new(dest, 0));
+            let sent = unsafe {
+                libc::sendto(
+                    self.0.as_raw_fd(),
+                    segment.as_ptr() as *const libc::c_void,
+                    segment.len(),
+                    0,
+                    &storage as *const _ as *const libc::sockaddr,
+                    len,
+                )
+            };
+            if sent < 0 { Err(io::Error::last_os_error()) } else { Ok(()) }
+        }
+
+        pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, IpAddr)> {
+            let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+            let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            let received = unsafe {
+                libc::recvfrom(
+                    self.0.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                    &mut storage as *mut _ as *mut libc::sockaddr,
+                    &mut len,
+                )
+            };
+            if received < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let from = match storage.ss_family as libc::c_int {
+                libc::AF_INET => {
+                    let addr = unsafe { &*(&storage as *const _ as *const libc::sockaddr_in) };
+                    IpAddr::V4(Ipv4Addr::from(addr.sin_addr.s_addr.to_ne_bytes()))
+                }
+                libc::AF_INET6 => {
+                    let addr = unsafe { &*(&storage as *const _ as *const libc::sockaddr_in6) };
+                    IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr))
+                }
+                _ => return Err(io::Error::from(io::ErrorKind::InvalidData)),
+            };
+            Ok((received as usize, from))
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+    use std::net::IpAddr;
+
+    use crate::connector::Connector;
+
+    pub struct RawSocket;
+
+    impl RawSocket {
+        pub fn open(_v6: bool, _connector: &Connector) -> io::Result<Self> {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "SYN scanning is only supported on Linux"))
+        }
+
+        pub fn send_to(&self, _segment: &[u8], _dest: IpAddr) -> io::Result<()> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+
+        pub fn recv_from(&self, _buf: &mut [u8]) -> io::Result<(usize, IpAddr)> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_syn_segment_checksums_and_parses() {
+        let source: IpAddr = "192.168.1.10".parse().unwrap();
+        let dest: SocketAddr = "192.168.1.20:443".parse().unwrap();
+        let syn = build_syn(source, dest, 40001, 0x1234_5678);
+        assert_eq!(syn.len(), 24);
+        assert_eq!(syn[13], TCP_SYN);
+        // Summing a segment that carries its own checksum gives zero
+        assert_eq!(tcp_checksum(source, dest.ip(), &syn), 0);
+
+        let v6: SocketAddr = "[2001:db8::2]:22".parse().unwrap();
+        let syn6 = build_syn("2001:db8::1".parse().unwrap(), v6, 40002, 7);
+        assert_eq!(tcp_checksum("2001:db8::1".parse().unwrap(), v6.ip(), &syn6), 0);
+
+        // A SYN/ACK from the target, as an IPv4 raw socket delivers it (20 byte IP header first)
+        let mut packet = vec![0x45, 0, 0, 44, 0, 0, 0, 0, 64, 6, 0, 0, 192, 168, 1, 20, 192, 168, 1, 10];
+        let mut reply = build_syn(dest.ip(), SocketAddr::new(source, 40001), 443, 99);
+        reply[8..12].copy_from_slice(&0x1234_5679u32.to_be_bytes());
+        reply[13] = TCP_SYN | TCP_ACK;
+        packet.extend_from_slice(&reply);
+        let parsed = parse_reply(&packet, dest.ip()).unwrap();
+        assert_eq!(parsed, Reply { from: dest, to_port: 40001, ack: 0x1234_5679, result: TcpResult::Open });
+
+        reply[13] = TCP_RST | TCP_ACK;
+        assert_eq!(parse_reply(&reply, v6.ip()).unwrap().result, TcpResult::Closed);
+        // Our own outgoing SYN is not a reply
+        assert!(parse_reply(&syn6, v6.ip()).is_none());
+    }
+
+    #[test]
+    fn test_source_ports_avoid_the_ephemeral_range() {
+        assert_eq!(parse_port_range("32768\t60999\n"), Some((32768, 60999)));
+        assert_eq!(parse_port_range("60999 32768"), None);
+        assert_eq!(parse_port_range("1024"), None);
+
+        assert_eq!(outside_range(32768, 60999), Some(61000..=65535));
+        // Too few ports above the range, so the ones below it are used
+        assert_eq!(outside_range(32768, 65000), Some(1024..=32767));
+        assert_eq!(outside_range(49152, 65535), Some(1024..=49151));
+        assert_eq!(outside_range(1024, 65535), None);
+
+        let Ok(prober) = SynProber::new(&Connector::default()) else { return };
+        let ports: Vec<u16> = (0..prober.source_ports.len() + 2).map(|_| prober.source_port()).collect();
+        assert!(ports.iter().all(|port| prober.source_ports.contains(port)));
+        // Every port is used once before the first comes round again
+        assert_eq!(ports[prober.source_ports.len()], ports[0]);
+        assert_eq!(ports[prober.source_ports.len() + 1], ports[1]);
+    }
+
+    #[tokio::test]
+    async fn test_syn_probe_loopback() {
+        // Raw sockets need root or CAP_NET_RAW; skip without them
+        let Ok(prober) = SynProber::new(&Connector::default()) else { return };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        let closed = {
+            let l = TcpListener::bind("127.0.0.1:0").unwrap();
+            l.local_addr().unwrap()
+        };
+        let timeout = Duration::from_millis(500);
+        assert_eq!(prober.probe(open, timeout).await, TcpResult::Open);
+        assert_eq!(prober.probe(closed, timeout).await, TcpResult::Closed);
+        // The handshake was never completed, so nothing is waiting to be accepted
+        listener.set_nonblocking(true).unwrap();
+        assert!(listener.accept().is_err());
+
+        if let Ok(listener) = TcpListener::bind("[::1]:0") {
+            if prober.supports(listener.local_addr().unwrap()) {
+                assert_eq!(prober.probe(listener.local_addr().unwrap(), timeout).await, TcpResult::Open);
+            }
+        }
+    }
+}
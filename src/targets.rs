@@ -0,0 +1,475 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::ports::parse_port;
+
+/// Upper bound on the number of hosts a target specification, a target list or a
+/// whole scan may expand to
+pub const MAX_HOSTS: usize = 65536;
+
+/// A host to scan, optionally pinned to a single port (from `host:port` lines)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TargetEntry {
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl TargetEntry {
+    pub fn host(host: String) -> Self {
+        Self { host, port: None }
+    }
+}
+
+/// Which resolved addresses of a host to scan
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpFamily {
+    /// The first address the resolver returns
+    #[default]
+    Auto,
+    /// Only IPv4 (A record) addresses
+    Ipv4,
+    /// Only IPv6 (AAAA record) addresses
+    Ipv6,
+    /// One IPv4 and one IPv6 address, scanned and reported separately
+    Both,
+}
+
+impl IpFamily {
+    /// Picks the addresses to scan out of a host's resolved addresses
+    pub fn select(self, addrs: &[IpAddr]) -> Vec<IpAddr> {
+        let first_v4 = addrs.iter().find(|ip| ip.is_ipv4()).copied();
+        let first_v6 = addrs.iter().find(|ip| ip.is_ipv6()).copied();
+
+        match self {
+            IpFamily::Auto => addrs.first().copied().into_iter().collect(),
+            IpFamily::Ipv4 => first_v4.into_iter().collect(),
+            IpFamily::Ipv6 => first_v6.into_iter().collect(),
+            IpFamily::Both => first_v4.into_iter().chain(first_v6).collect(),
+        }
+    }
+}
+
+/// A target whose host has been resolved to a concrete address.
+/// The original host string is kept for display, HTTP Host headers and TLS SNI.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolvedTarget {
+    pub host: String,
+    pub ip: IpAddr,
+    pub port: Option<u16>,
+}
+
+/// Resolves a hostname or IP literal to its addresses (A and AAAA records),
+/// in the order the system resolver prefers them
+pub fn resolve_host(host: &str) -> io::Result<Vec<IpAddr>> {
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = literal.parse::<IpAddr>() {
+        return Ok(vec![ip]);
+    }
+
+    let mut addrs: Vec<IpAddr> = Vec::new();
+    for socket_addr in (host, 0).to_socket_addrs()? {
+        if !addrs.contains(&socket_addr.ip()) {
+            addrs.push(socket_addr.ip());
+        }
+    }
+
+    if addrs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no addresses found"));
+    }
+
+    Ok(addrs)
+}
+
+/// Resolves every target, looking each distinct host up only once.
+/// `family` decides which of a host's addresses are scanned; with `IpFamily::Both`
+/// a dual-stack host produces one target per address family.
+/// Hosts that fail to resolve are returned as error messages instead of being scanned.
+pub fn resolve_targets(entries: &[TargetEntry], family: IpFamily) -> (Vec<ResolvedTarget>, Vec<String>) {
+    let mut cache: HashMap<&str, Vec<IpAddr>> = HashMap::new();
+    let mut resolved = Vec::new();
+    let mut errors = Vec::new();
+
+    for entry in entries {
+        let ips = cache.entry(entry.host.as_str()).or_insert_with(|| {
+            match resolve_host(&entry.host) {
+                Ok(addrs) => {
+                    let selected = family.select(&addrs);
+                    if selected.is_empty() {
+                        let wanted = match family {
+                            IpFamily::Ipv4 => "IPv4",
+                            IpFamily::Ipv6 => "IPv6",
+                            IpFamily::Auto | IpFamily::Both => "usable",
+                        };
+                        errors.push(format!("{} has no {} address", entry.host, wanted));
+                    }
+                    selected
+                }
+                Err(e) => {
+                    errors.push(format!("Failed to resolve {}: {}", entry.host, e));
+                    Vec::new()
+                }
+            }
+        });
+
+        for &ip in ips.iter() {
+            resolved.push(ResolvedTarget {
+                host: entry.host.clone(),
+                ip,
+                port: entry.port,
+            });
+        }
+    }
+
+    (resolved, errors)
+}
+
+/// Returns each distinct (host, address) pair in scan order, the unit results are grouped by
+pub fn unique_addresses(targets: &[ResolvedTarget]) -> Vec<(String, IpAddr)> {
+    let mut seen = HashSet::new();
+    targets
+        .iter()
+        .map(|t| (t.host.clone(), t.ip))
+        .filter(|key| seen.insert(key.clone()))
+        .collect()
+}
+
+/// Parses a target specification into a list of hosts.
+/// The specification is a comma-separated list where each entry may be:
+/// - a hostname or IP address (`example.com`, `10.0.0.1`, `::1`)
+/// - a CIDR block (`10.0.0.0/24`, `fd00::/120`)
+/// - a dash range on the last octet (`10.0.0.1-50`) or between two addresses (`10.0.0.1-10.0.0.50`)
+///
+/// Duplicate hosts are removed while preserving order.
+pub fn parse_targets(spec: &str) -> Result<Vec<String>, String> {
+    let mut hosts: Vec<String> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+
+    for entry in spec.split(',') {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+
+        for host in expand_entry(entry)? {
+            if seen.insert(host.clone()) {
+                hosts.push(host);
+            }
+            if hosts.len() > MAX_HOSTS {
+                return Err(format!("Target specification expands to more than {} hosts", MAX_HOSTS));
+            }
+        }
+    }
+
+    if hosts.is_empty() {
+        return Err("No targets specified".to_string());
+    }
+
+    Ok(hosts)
+}
+
+/// Parses a target list, one entry per line.
+/// Each line may hold anything `parse_targets` accepts, or a `host:port` /
+/// `[v6addr]:port` pair that is only scanned on that port.
+/// Blank lines and `#` comments are ignored.
+pub fn parse_target_lines(text: &str) -> Result<Vec<TargetEntry>, String> {
+    let mut entries: Vec<TargetEntry> = Vec::new();
+    let mut seen: HashSet<TargetEntry> = HashSet::new();
+
+    for (line_no, line) in text.lines().enumerate() {
+        let line = strip_comment(line);
+        if line.is_empty() {
+            continue;
+        }
+
+        let (spec, port) = split_host_port(line)
+            .map_err(|e| format!("line {}: {}", line_no + 1, e))?;
+        let hosts = parse_targets(spec)
+            .map_err(|e| format!("line {}: {}", line_no + 1, e))?;
+
+        for host in hosts {
+            let entry = TargetEntry { host, port };
+            if seen.insert(entry.clone()) {
+                entries.push(entry);
+            }
+        }
+        if entries.len() > MAX_HOSTS {
+            return Err(format!("line {}: target list expands to more than {} hosts", line_no + 1, MAX_HOSTS));
+        }
+    }
+
+    if entries.is_empty() {
+        return Err("Target list is empty".to_string());
+    }
+
+    Ok(entries)
+}
+
+/// Reads a target list from a file, or from stdin when `path` is `-`
+pub fn read_target_file(path: &str) -> Result<Vec<TargetEntry>, String> {
+    let text = read_input(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    parse_target_lines(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Returns each distinct host in the order it first appears
+pub fn unique_hosts<'a>(hosts: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    hosts
+        .into_iter()
+        .filter(|host| seen.insert(*host))
+        .map(|host| host.to_string())
+        .collect()
+}
+
+/// Reads the whole contents of a file, or of stdin when `path` is `-`
+pub fn read_input(path: &str) -> io::Result<String> {
+    if path == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        Ok(text)
+    } else {
+        fs::read_to_string(path)
+    }
+}
+
+/// Removes a trailing `#` comment and surrounding whitespace from a line
+pub fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(pos) => line[..pos].trim(),
+        None => line.trim(),
+    }
+}
+
+/// Splits an optional `:port` suffix off a target line.
+/// Bare IPv6 addresses contain colons themselves, so a port is only
+/// recognised on them when the address is bracketed (`[::1]:22`).
+fn split_host_port(line: &str) -> Result<(&str, Option<u16>), String> {
+    if let Some(rest) = line.strip_prefix('[') {
+        let (addr, after) = rest
+            .split_once(']')
+            .ok_or_else(|| format!("Unterminated '[' in {}", line))?;
+        let port = match after.strip_prefix(':') {
+            Some(port) => Some(parse_port(port)?),
+            None if after.is_empty() => None,
+            None => return Err(format!("Unexpected text after ']' in {}", line)),
+        };
+        return Ok((addr, port));
+    }
+
+    if line.matches(':').count() == 1 {
+        let (host, port) = line.split_once(':').unwrap();
+        return Ok((host, Some(parse_port(port)?)));
+    }
+
+    Ok((line, None))
+}
+
+/// Expands a single entry of a target specification
+fn expand_entry(entry: &str) -> Result<Vec<String>, String> {
+    if let Some((addr, prefix)) = entry.split_once('/') {
+        return expand_cidr(addr, prefix);
+    }
+
+    // Hostnames may contain dashes, so only treat the entry as a range
+    // when the part before the dash is an IPv4 address
+    if let Some((start, end)) = entry.split_once('-') {
+        if let Ok(start) = start.parse::<Ipv4Addr>() {
+            return expand_range(start, end);
+        }
+    }
+
+    // Accept bracketed IPv6 literals such as `[::1]` but store them bare
+    if let Some(literal) = entry.strip_prefix('[').and_then(|e| e.strip_suffix(']')) {
+        if let Ok(v6) = literal.parse::<Ipv6Addr>() {
+            return Ok(vec![v6.to_string()]);
+        }
+    }
+
+    Ok(vec![entry.to_string()])
+}
+
+/// Expands a CIDR block into every address it contains
+fn expand_cidr(addr: &str, prefix: &str) -> Result<Vec<String>, String> {
+    let ip: IpAddr = addr
+        .parse()
+        .map_err(|_| format!("Invalid CIDR address: {}", addr))?;
+    let prefix: u32 = prefix
+        .parse()
+        .map_err(|_| format!("Invalid CIDR prefix: {}", prefix))?;
+
+    match ip {
+        IpAddr::V4(v4) => {
+            if prefix > 32 {
+                return Err(format!("Invalid IPv4 prefix length: /{}", prefix));
+            }
+            let host_bits = 32 - prefix;
+            if (1u64 << host_bits) > MAX_HOSTS as u64 {
+                return Err(format!("CIDR block {}/{} is too large", addr, prefix));
+            }
+            let mask = if prefix == 0 { 0 } else { u32::MAX << host_bits };
+            let network = u32::from(v4) & mask;
+            Ok((0..(1u32 << host_bits))
+                .map(|i| Ipv4Addr::from(network + i).to_string())
+                .collect())
+        }
+        IpAddr::V6(v6) => {
+            if prefix > 128 {
+                return Err(format!("Invalid IPv6 prefix length: /{}", prefix));
+            }
+            let host_bits = 128 - prefix;
+            if host_bits >= 64 || (1u64 << host_bits) > MAX_HOSTS as u64 {
+                return Err(format!("CIDR block {}/{} is too large", addr, prefix));
+            }
+            let mask = if prefix == 0 { 0 } else { u128::MAX << host_bits };
+            let network = u128::from(v6) & mask;
+            Ok((0..(1u128 << host_bits))
+                .map(|i| Ipv6Addr::from(network + i).to_string())
+                .collect())
+        }
+    }
+}
+
+/// Expands a dash range such as `10.0.0.1-50` or `10.0.0.1-10.0.0.50`
+fn expand_range(start: Ipv4Addr, end: &str) -> Result<Vec<String>, String> {
+    let end = if let Ok(last_octet) = end.parse::<u8>() {
+        let [a, b, c, _] = start.octets();
+        Ipv4Addr::new(a, b, c, last_octet)
+    } else {
+        end.parse::<Ipv4Addr>()
+            .map_err(|_| format!("Invalid range end: {}", end))?
+    };
+
+    let (start, end) = (u32::from(start), u32::from(end));
+    if end < start {
+        return Err(format!("Range end {} is before range start {}", Ipv4Addr::from(end), Ipv4Addr::from(start)));
+    }
+    if (end - start) as usize >= MAX_HOSTS {
+        return Err("Address range is too large".to_string());
+    }
+
+    Ok((start..=end).map(|ip| Ipv4Addr::from(ip).to_string()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_host() {
+        assert_eq!(parse_targets("example.com").unwrap(), vec!["example.com"]);
+        assert_eq!(parse_targets("my-host.local").unwrap(), vec!["my-host.local"]);
+    }
+
+    #[test]
+    fn test_cidr() {
+        let hosts = parse_targets("10.0.0.5/30").unwrap();
+        assert_eq!(hosts, vec!["10.0.0.4", "10.0.0.5", "10.0.0.6", "10.0.0.7"]);
+        assert_eq!(parse_targets("192.168.1.0/24").unwrap().len(), 256);
+        assert_eq!(parse_targets("fd00::/126").unwrap().len(), 4);
+        assert!(parse_targets("10.0.0.0/8").is_err());
+    }
+
+    #[test]
+    fn test_dash_range() {
+        let hosts = parse_targets("10.0.0.1-3").unwrap();
+        assert_eq!(hosts, vec!["10.0.0.1", "10.0.0.2", "10.0.0.3"]);
+        let hosts = parse_targets("10.0.0.254-10.0.1.1").unwrap();
+        assert_eq!(hosts.len(), 4);
+        assert!(parse_targets("10.0.0.50-1").is_err());
+    }
+
+    #[test]
+    fn test_target_lines() {
+        let text = "# inventory\n10.0.0.1-2\n\nweb.internal:8443  # admin\n[::1]:22\n::1\n10.0.0.1\n";
+        let entries = parse_target_lines(text).unwrap();
+        assert_eq!(entries, vec![
+            TargetEntry { host: "10.0.0.1".to_string(), port: None },
+            TargetEntry { host: "10.0.0.2".to_string(), port: None },
+            TargetEntry { host: "web.internal".to_string(), port: Some(8443) },
+            TargetEntry { host: "::1".to_string(), port: Some(22) },
+            TargetEntry { host: "::1".to_string(), port: None },
+        ]);
+        assert_eq!(unique_hosts(entries.iter().map(|e| e.host.as_str())), vec!["10.0.0.1", "10.0.0.2", "web.internal", "::1"]);
+
+        let err = parse_target_lines("10.0.0.1\nhost:99999\n").unwrap_err();
+        assert!(err.starts_with("line 2"));
+
+        // The cap applies to the whole list, not to each line
+        let err = parse_target_lines("10.0.0.0/16\n10.1.0.0/16\n").unwrap_err();
+        assert_eq!(err, format!("line 2: target list expands to more than {} hosts", MAX_HOSTS));
+    }
+
+    #[test]
+    fn test_resolve_targets() {
+        let entries = vec![
+            TargetEntry::host("10.0.0.1".to_string()),
+            TargetEntry { host: "10.0.0.2".to_string(), port: Some(22) },
+            TargetEntry::host("[192.0.2.1]".to_string()),
+            TargetEntry::host("2001:db8::1".to_string()),
+            TargetEntry { host: "2001:db8::1".to_string(), port: Some(80) },
+        ];
+        let (resolved, errors) = resolve_targets(&entries, IpFamily::Ipv4);
+
+        assert_eq!(resolved.len(), 3);
+        assert_eq!(resolved[0].ip, "10.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(resolved[1].ip, "10.0.0.2".parse::<IpAddr>().unwrap());
+        assert_eq!(resolved[1].port, Some(22));
+        assert_eq!(resolved[2].ip, "192.0.2.1".parse::<IpAddr>().unwrap());
+        assert_eq!(resolved[2].host, "[192.0.2.1]");
+
+        // A failing host is reported once, not per entry
+        assert_eq!(errors, vec!["2001:db8::1 has no IPv4 address".to_string()]);
+    }
+
+    #[test]
+    #[ignore = "needs the system resolver"]
+    fn test_resolve_hostnames() {
+        let entries = vec![
+            TargetEntry::host("localhost".to_string()),
+            TargetEntry::host("does-not-exist.invalid".to_string()),
+            TargetEntry { host: "does-not-exist.invalid".to_string(), port: Some(80) },
+        ];
+        let (resolved, errors) = resolve_targets(&entries, IpFamily::Auto);
+
+        assert_eq!(resolved.len(), 1);
+        assert!(resolved[0].ip.is_loopback());
+        assert_eq!(resolved[0].host, "localhost");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("does-not-exist.invalid"));
+    }
+
+    #[test]
+    fn test_ip_family_select() {
+        let addrs: Vec<IpAddr> = vec![
+            "2001:db8::1".parse().unwrap(),
+            "192.0.2.1".parse().unwrap(),
+            "192.0.2.2".parse().unwrap(),
+        ];
+        assert_eq!(IpFamily::Auto.select(&addrs), vec![addrs[0]]);
+        assert_eq!(IpFamily::Ipv4.select(&addrs), vec![addrs[1]]);
+        assert_eq!(IpFamily::Ipv6.select(&addrs), vec![addrs[0]]);
+        assert_eq!(IpFamily::Both.select(&addrs), vec![addrs[1], addrs[0]]);
+        assert!(IpFamily::Ipv6.select(&addrs[1..]).is_empty());
+
+        let (resolved, errors) = resolve_targets(&[TargetEntry::host("[::1]".to_string())], IpFamily::Ipv4);
+        assert!(resolved.is_empty());
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_bracketed_ipv6() {
+        assert_eq!(parse_targets("[::1],[fd00::1]").unwrap(), vec!["::1", "fd00::1"]);
+    }
+
+    #[test]
+    fn test_mixed_list() {
+        let hosts = parse_targets("10.0.0.1-2, example.com,10.0.0.2").unwrap();
+        assert_eq!(hosts, vec!["10.0.0.1", "10.0.0.2", "example.com"]);
+        assert!(parse_targets(" , ").is_err());
+    }
+}
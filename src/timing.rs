@@ -0,0 +1,225 @@
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::rtt::RttTable;
+
+/// Named timing presets, from slow and stealthy to fast and noisy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimingTemplate {
+    /// One probe at a time, one per second, with very long timeouts
+    Paranoid,
+    /// A handful of probes in flight, ten per second
+    Sneaky,
+    /// Generous timeouts and a 100 probes per second cap, for fragile networks
+    Polite,
+    /// Balanced defaults for most networks
+    #[default]
+    Normal,
+    /// Short timeouts for fast, reliable networks
+    Aggressive,
+    /// Minimal timeouts and no retries; trades accuracy for speed
+    Insane,
+}
+
+impl TimingTemplate {
+    pub fn timing(self) -> Timing {
+        // (timeout, min, max, udp, probe connect, probe read, service budget) in ms,
+        // then retries, concurrency and rate limit
+        let (timeout, min, max, udp, connect, read, budget, retries, concurrency, max_rate) = match self {
+            TimingTemplate::Paranoid => (5000, 1000, 10000, 5000, 5000, 10000, 60000, 3, 1, Some(1)),
+            TimingTemplate::Sneaky => (3000, 500, 10000, 3000, 3000, 5000, 30000, 3, 10, Some(10)),
+            TimingTemplate::Polite => (1500, 100, 5000, 1000, 1000, 2000, 10000, 2, 50, Some(100)),
+            TimingTemplate::Normal => (1000, 50, 3000, 100, 500, 1000, 5000, 1, 500, None),
+            TimingTemplate::Aggressive => (500, 25, 1250, 75, 250, 500, 2000, 1, 1000, None),
+            TimingTemplate::Insane => (250, 10, 300, 50, 150, 250, 1000, 0, 2000, None),
+        };
+        Timing {
+            timeout_ms: timeout,
+            min_timeout_ms: min,
+            max_timeout_ms: max,
+            udp_timeout_ms: udp,
+            probe_connect_ms: connect,
+            probe_read_ms: read,
+            service_budget_ms: budget,
+            retries,
+            concurrency,
+            max_rate,
+        }
+    }
+}
+
+/// Every timeout, retry and pacing setting of a scan, covering both the port
+/// probes and the fingerprinting probes run against open ports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timing {
+    /// Connect timeout used until a host's round-trip time has been measured
+    pub timeout_ms: u64,
+    /// Bounds for the per-host connect timeouts derived from measured RTT
+    pub min_timeout_ms: u64,
+    pub max_timeout_ms: u64,
+    pub udp_timeout_ms: u64,
+    /// Connect timeout of each fingerprinting probe
+    pub probe_connect_ms: u64,
+    /// Read and write timeout of each fingerprinting probe
+    pub probe_read_ms: u64,
+    /// Total time spent fingerprinting one open port, across all probes
+    pub service_budget_ms: u64,
+    /// How many times a probe that timed out is resent before giving up
+    pub retries: u32,
+    /// Maximum number of work items probed at the same time
+    pub concurrency: usize,
+    /// Global limit on port probes and discovery pings sent per second; fingerprinting connections
+    /// to open ports are not counted
+    pub max_rate: Option<u32>,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        TimingTemplate::Normal.timing()
+    }
+}
+
+/// Individual settings given explicitly, which win over the template's values
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimingOverrides {
+    pub timeout_ms: Option<u64>,
+    pub min_timeout_ms: Option<u64>,
+    pub max_timeout_ms: Option<u64>,
+    pub udp_timeout_ms: Option<u64>,
+    /// Sets both the connect and the read timeout of fingerprinting probes
+    pub probe_timeout_ms: Option<u64>,
+    pub retries: Option<u32>,
+    pub concurrency: Option<usize>,
+    /// `Some(None)` lifts the template's rate limit
+    pub max_rate: Option<Option<u32>>,
+}
+
+impl Timing {
+    pub fn with_overrides(mut self, overrides: &TimingOverrides) -> Self {
+        let TimingOverrides {
+            timeout_ms,
+            min_timeout_ms,
+            max_timeout_ms,
+            udp_timeout_ms,
+            probe_timeout_ms,
+            retries,
+            concurrency,
+            max_rate,
+        } = *overrides;
+
+        self.timeout_ms = timeout_ms.unwrap_or(self.timeout_ms);
+        self.min_timeout_ms = min_timeout_ms.unwrap_or(self.min_timeout_ms);
+        self.max_timeout_ms = max_timeout_ms.unwrap_or(self.max_timeout_ms);
+        self.udp_timeout_ms = udp_timeout_ms.unwrap_or(self.udp_timeout_ms);
+        // A connect timeout given without a probe timeout applies to the
+        // fingerprinting connects as well
+        if let Some(ms) = probe_timeout_ms {
+            self.probe_connect_ms = ms;
+            self.probe_read_ms = ms;
+        } else if let Some(ms) = timeout_ms {
+            self.probe_connect_ms = ms;
+        }
+        self.retries = retries.unwrap_or(self.retries);
+        self.concurrency = concurrency.unwrap_or(self.concurrency);
+        if let Some(max_rate) = max_rate {
+            self.max_rate = max_rate;
+        }
+        self
+    }
+
+    /// A fresh per-host RTT table with this scan's connect timeout bounds
+    pub fn rtt_table(&self) -> RttTable {
+        RttTable::new(self.timeout_ms, self.min_timeout_ms, self.max_timeout_ms)
+    }
+
+    pub fn probe_connect(&self) -> Duration {
+        Duration::from_millis(self.probe_connect_ms)
+    }
+
+    pub fn probe_read(&self) -> Duration {
+        Duration::from_millis(self.probe_read_ms)
+    }
+
+    /// Deadline for fingerprinting a port whose probes start now
+    pub fn service_deadline(&self) -> Instant {
+        Instant::now() + Duration::from_millis(self.service_budget_ms)
+    }
+
+    /// The timing for the next fingerprinting probe, with its timeouts cut down
+    /// to what is left before `deadline`; `None` once the budget is spent
+    pub fn within(&self, deadline: Instant) -> Option<Timing> {
+        let left = deadline.saturating_duration_since(Instant::now()).as_millis() as u64;
+        if left == 0 {
+            return None;
+        }
+        Some(Timing {
+            probe_connect_ms: self.probe_connect_ms.min(left),
+            probe_read_ms: self.probe_read_ms.min(left),
+            ..*self
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_templates_get_faster_in_order() {
+        let templates = TimingTemplate::value_variants();
+        for pair in templates.windows(2) {
+            let (slower, faster) = (pair[0].timing(), pair[1].timing());
+            assert!(slower.timeout_ms >= faster.timeout_ms, "{:?}", pair);
+            assert!(slower.probe_read_ms >= faster.probe_read_ms, "{:?}", pair);
+            assert!(slower.service_budget_ms >= faster.service_budget_ms, "{:?}", pair);
+            assert!(slower.concurrency <= faster.concurrency, "{:?}", pair);
+        }
+
+        // Every template leaves room for at least one full probe within the budget
+        for template in templates {
+            let timing = template.timing();
+            assert!(timing.probe_connect_ms + timing.probe_read_ms <= timing.service_budget_ms, "{:?}", template);
+            assert!(timing.min_timeout_ms <= timing.timeout_ms && timing.timeout_ms <= timing.max_timeout_ms);
+        }
+    }
+
+    #[test]
+    fn test_overrides_win_over_template() {
+        let overrides = TimingOverrides {
+            timeout_ms: Some(200),
+            probe_timeout_ms: Some(300),
+            retries: Some(0),
+            ..Default::default()
+        };
+        let timing = TimingTemplate::Polite.timing().with_overrides(&overrides);
+        assert_eq!(timing.timeout_ms, 200);
+        assert_eq!((timing.probe_connect_ms, timing.probe_read_ms), (300, 300));
+        assert_eq!(timing.retries, 0);
+        // Settings that were not overridden keep the template's value
+        assert_eq!(timing.max_rate, Some(100));
+        assert_eq!(timing.concurrency, 50);
+
+        // The template's rate can be lifted, and the connect timeout reaches
+        // fingerprinting when no probe timeout is given
+        let overrides = TimingOverrides { timeout_ms: Some(200), max_rate: Some(None), ..Default::default() };
+        let timing = TimingTemplate::Polite.timing().with_overrides(&overrides);
+        assert_eq!(timing.max_rate, None);
+        assert_eq!((timing.probe_connect_ms, timing.probe_read_ms), (200, 2000));
+    }
+
+    #[test]
+    fn test_within_clamps_to_remaining_budget() {
+        let timing = Timing::default();
+        let clamped = timing.within(Instant::now() + Duration::from_millis(200)).unwrap();
+        assert!(clamped.probe_connect_ms <= 200 && clamped.probe_read_ms <= 200);
+        assert_eq!(clamped.udp_timeout_ms, timing.udp_timeout_ms);
+
+        let far = timing.within(Instant::now() + Duration::from_secs(60)).unwrap();
+        assert_eq!(far.probe_read_ms, timing.probe_read_ms);
+
+        assert!(timing.within(Instant::now()).is_none());
+    }
+}
@@ -1,34 +1,297 @@
-use std::net::UdpSocket;
+use std::net::SocketAddr;
 use std::time::Duration;
-use std::io::ErrorKind;
+use std::io::{self, ErrorKind};
 
-/// Sends a zero-byte UDP packet to the given address and port.
-/// Returns `true` if the port is likely open or filtered (no ICMP unreachable),
-/// or `false` if a "ConnectionRefused" ICMP message is received (port closed).
-pub fn scan_udp(addr: &str, port: u16, timeout_ms: u64) -> bool {
-    let socket_addr = (addr, port);
+use tokio::io::Interest;
 
-    // Bind a local ephemeral UDP socket
-    let socket = match UdpSocket::bind("0.0.0.0:0") {
+use crate::connector::Connector;
+use crate::protocols::udp_probes::payload_for_port;
+use crate::service_info::PortState;
+
+/// Outcome of a UDP probe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UdpResult {
+    /// A datagram came back; holds the reply payload
+    Open(Vec<u8>),
+    /// Nothing came back: the port may be open and silent, or filtered
+    OpenFiltered,
+    /// An ICMP port-unreachable came back
+    Closed,
+    /// Another ICMP error came back, typically a firewall rejecting the datagram
+    Filtered(IcmpError),
+    /// An ICMP host or network unreachable came back
+    HostUnreachable(IcmpError),
+}
+
+impl UdpResult {
+    pub fn state(&self) -> PortState {
+        match self {
+            UdpResult::Open(_) => PortState::Open,
+            UdpResult::OpenFiltered => PortState::OpenFiltered,
+            UdpResult::Closed => PortState::Closed,
+            UdpResult::Filtered(_) | UdpResult::HostUnreachable(_) => PortState::Filtered,
+        }
+    }
+
+    pub fn reason(&self) -> String {
+        match self {
+            UdpResult::Open(_) => "udp-response".to_string(),
+            UdpResult::OpenFiltered => "no-response".to_string(),
+            UdpResult::Closed => "port-unreachable".to_string(),
+            UdpResult::Filtered(icmp) | UdpResult::HostUnreachable(icmp) => icmp.to_string(),
+        }
+    }
+}
+
+/// An ICMP or ICMPv6 error received in response to a probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcmpError {
+    pub v6: bool,
+    pub icmp_type: u8,
+    pub code: u8,
+}
+
+impl IcmpError {
+    /// Classifies the error the way nmap does: port-unreachable means closed,
+    /// host/network errors mean the host is unreachable, anything else is a firewall
+    pub fn classify(self) -> UdpResult {
+        match (self.v6, self.icmp_type, self.code) {
+            (false, 3, 3) | (true, 1, 4) => UdpResult::Closed,
+            (false, 3, 0 | 1 | 5 | 6 | 7 | 8 | 11 | 12) | (false, 11, _) => UdpResult::HostUnreachable(self),
+            (true, 1, 0 | 2 | 3) | (true, 3, _) => UdpResult::HostUnreachable(self),
+            _ => UdpResult::Filtered(self),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match (self.v6, self.icmp_type, self.code) {
+            (false, 3, 0) | (false, 3, 6) => "net-unreachable",
+            (false, 3, 1) | (false, 3, 7) => "host-unreachable",
+            (false, 3, 2) => "proto-unreachable",
+            (false, 3, 3) | (true, 1, 4) => "port-unreachable",
+            (false, 3, 9 | 10 | 13) | (true, 1, 1) => "admin-prohibited",
+            (false, 11, _) | (true, 3, _) => "time-exceeded",
+            (true, 1, 0) => "no-route",
+            (true, 1, 2) => "beyond-scope",
+            (true, 1, 3) => "addr-unreachable",
+            (true, 1, 5) => "policy-failed",
+            (true, 1, 6) => "reject-route",
+            _ => "icmp-error",
+        }
+    }
+}
+
+impl std::fmt::Display for IcmpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let proto = if self.v6 { "icmpv6" } else { "icmp" };
+        write!(f, "{} ({} {}/{})", self.name(), proto, self.icmp_type, self.code)
+    }
+}
+
+/// Sends the port's protocol payload (or an empty datagram) to the given address.
+/// Only an actual reply proves the port is open; silence is reported as
+/// `OpenFiltered`, and ICMP errors are classified into closed, filtered or
+/// host-unreachable (on Linux, where the socket error queue exposes the ICMP type).
+pub async fn scan_udp(addr: SocketAddr, timeout_ms: u64, connector: &Connector) -> UdpResult {
+    // Bind a local ephemeral UDP socket of the same address family as the target
+    let socket = match connector.udp_socket(addr) {
+        Ok(s) => s,
+        Err(_) => return UdpResult::OpenFiltered,
+    };
+
+    // Ask the kernel to queue every ICMP error with its type and code
+    #[cfg(target_os = "linux")]
+    let _ = errqueue::enable(&socket, addr.is_ipv6());
+
+    // Hand the socket to tokio so waiting for a reply does not block a thread
+    let socket = match socket.set_nonblocking(true).and_then(|_| tokio::net::UdpSocket::from_std(socket)) {
         Ok(s) => s,
-        Err(_) => return false,
+        Err(_) => return UdpResult::OpenFiltered,
     };
 
-    // Set a read timeout to avoid blocking indefinitely
-    if socket.set_read_timeout(Some(Duration::from_millis(timeout_ms)))
-    .is_err() {
-        return false;
+    // Connect the socket so ICMP errors for this destination are reported back to us
+    if socket.connect(addr).await.is_err() {
+        return UdpResult::OpenFiltered;
+    }
+
+    // Send a request the service on this port is expected to answer
+    let _ = socket.send(payload_for_port(addr.port())).await;
+
+    // Try to receive a reply or ICMP error, giving up after the timeout
+    let mut buf = [0u8; 2048];
+    match tokio::time::timeout(Duration::from_millis(timeout_ms), recv_or_error(&socket, &mut buf)).await {
+        Ok(Ok(n)) => UdpResult::Open(buf[..n].to_vec()),  // Received a reply => open
+        Ok(Err(e)) => {
+            #[cfg(target_os = "linux")]
+            if let Some(icmp) = errqueue::read(&socket) {
+                return icmp.classify();
+            }
+
+            match e.kind() {
+                ErrorKind::ConnectionRefused => UdpResult::Closed,  // ICMP unreachable => closed
+                _ => UdpResult::OpenFiltered,
+            }
+        }
+        Err(_) => UdpResult::OpenFiltered,  // Timeout => open or filtered
     }
+}
+
+/// Waits for a datagram or a socket error. A plain `recv` is only woken by
+/// readable readiness, which a pending ICMP error (EPOLLERR) does not signal.
+async fn recv_or_error(socket: &tokio::net::UdpSocket, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        let ready = socket.ready(Interest::READABLE | Interest::ERROR).await?;
+        match socket.try_recv(buf) {
+            Err(e) if e.kind() == ErrorKind::WouldBlock && ready.is_error() => {
+                return Err(io::Error::other("socket error"));
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+            result => return result,
+        }
+    }
+}
+
+/// Access to the Linux socket error queue (`IP_RECVERR` / `IPV6_RECVERR`)
+#[cfg(target_os = "linux")]
+mod errqueue {
+    use std::io;
+    use std::mem;
+    use std::os::fd::AsRawFd;
+
+    use super::IcmpError;
+
+    pub fn enable(socket: &impl AsRawFd, v6: bool) -> io::Result<()> {
+        let (level, name) = if v6 {
+            (libc::SOL_IPV6, libc::IPV6_RECVERR)
+        } else {
+            (libc::SOL_IP, libc::IP_RECVERR)
+        };
+        let on: libc::c_int = 1;
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &on as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+    }
+
+    /// Pops the next queued error and returns it if it came from an ICMP message
+    pub fn read(socket: &impl AsRawFd) -> Option<IcmpError> {
+        let mut data = [0u8; 512];
+        // u64 elements keep the control buffer aligned for cmsghdr
+        let mut control = [0u64; 64];
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+
+        let n = unsafe {
+            libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT)
+        };
+        if n < 0 {
+            return None;
+        }
+
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        while !cmsg.is_null() {
+            let header = unsafe { &*cmsg };
+            let is_recverr = (header.cmsg_level == libc::SOL_IP && header.cmsg_type == libc::IP_RECVERR)
+                || (header.cmsg_level == libc::SOL_IPV6 && header.cmsg_type == libc::IPV6_RECVERR);
+
+            if is_recverr {
+                let err = unsafe {
+                    (libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err).read_unaligned()
+                };
+                if err.ee_origin == libc::SO_EE_ORIGIN_ICMP || err.ee_origin == libc::SO_EE_ORIGIN_ICMP6 {
+                    return Some(IcmpError {
+                        v6: err.ee_origin == libc::SO_EE_ORIGIN_ICMP6,
+                        icmp_type: err.ee_type,
+                        code: err.ee_code,
+                    });
+                }
+            }
+
+            cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::thread;
+
+    #[tokio::test]
+    async fn test_scan_udp_reply_is_open() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            if let Ok((_, peer)) = server.recv_from(&mut buf) {
+                let _ = server.send_to(b"pong", peer);
+            }
+        });
+
+        assert_eq!(scan_udp(addr, 1000, &Connector::default()).await, UdpResult::Open(b"pong".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_scan_udp_closed_and_silent() {
+        // A bound socket that never answers looks open|filtered
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(scan_udp(silent.local_addr().unwrap(), 200, &Connector::default()).await, UdpResult::OpenFiltered);
+
+        // Once it is gone the kernel answers with port-unreachable
+        let closed = silent.local_addr().unwrap();
+        drop(silent);
+        assert_eq!(scan_udp(closed, 500, &Connector::default()).await, UdpResult::Closed);
+    }
+
+    #[test]
+    fn test_classify_icmp() {
+        let icmp = |v6, icmp_type, code| IcmpError { v6, icmp_type, code };
+
+        assert_eq!(icmp(false, 3, 3).classify(), UdpResult::Closed);
+        assert_eq!(icmp(true, 1, 4).classify(), UdpResult::Closed);
+        assert_eq!(icmp(false, 3, 1).classify(), UdpResult::HostUnreachable(icmp(false, 3, 1)));
+        assert_eq!(icmp(true, 1, 3).classify(), UdpResult::HostUnreachable(icmp(true, 1, 3)));
+        assert_eq!(icmp(false, 3, 13).classify(), UdpResult::Filtered(icmp(false, 3, 13)));
+        assert_eq!(icmp(true, 1, 1).classify(), UdpResult::Filtered(icmp(true, 1, 1)));
+
+        let result = icmp(false, 3, 10).classify();
+        assert_eq!(result.state(), PortState::Filtered);
+        assert_eq!(result.reason(), "admin-prohibited (icmp 3/10)");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_error_queue_reports_port_unreachable() {
+        let closed = {
+            let s = UdpSocket::bind("127.0.0.1:0").unwrap();
+            s.local_addr().unwrap()
+        };
 
-    // Send an empty datagram to the target
-    let _ = socket.send_to(&[], socket_addr);
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        errqueue::enable(&socket, false).unwrap();
+        socket.connect(closed).unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        socket.send(b"x").unwrap();
 
-    // Try to receive a reply or ICMP error
-    let mut buf = [0u8; 1];
-    match socket.recv_from(&mut buf) {
-        Ok(_) => true,  // Received some packet => open/filtered
-        Err(ref e) if e.kind() == ErrorKind::WouldBlock => true,  // Timeout => open/filtered
-        Err(ref e) if e.kind() == ErrorKind::ConnectionRefused => false,  // ICMP unreachable => closed
-        Err(_) => false,  // treat as closed
+        let mut buf = [0u8; 16];
+        assert!(socket.recv(&mut buf).is_err());
+        assert_eq!(errqueue::read(&socket), Some(IcmpError { v6: false, icmp_type: 3, code: 3 }));
     }
-}
\ No newline at end of file
+}
@@ -5,13 +5,17 @@ use axum::{
     Form, Router,
 };
 use once_cell::sync::Lazy;
-use serde::Deserialize;
-use std::sync::{mpsc, Arc, Mutex, RwLock};
-use std::thread;
+use serde::{Deserialize, Deserializer};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
 
-use crate::scanner::worker_loop;
-use crate::signatures::SignatureMatcher;
-use crate::web_state::ScanState;
+use port_scanner::ports::PortSpec;
+use port_scanner::service_info::group_by_host;
+use port_scanner::sink::PrettySink;
+use port_scanner::{IpFamily, PortState, Scanner, SignatureMatcher, TimingOverrides, TimingTemplate};
+
+use crate::web_state::{ScanState, ScanStatus};
 
 // Global state for the current scan
 static CURRENT_SCAN: Lazy<RwLock<Option<ScanState>>> = Lazy::new(|| RwLock::new(None));
@@ -26,24 +30,113 @@ pub struct ScanRequest {
     target: String,
     start_port: u16,
     end_port: u16,
-    threads: usize,
-    timeout_ms: u64,
-    udp_timeout_ms: u64,
+    /// Optional port specification that overrides the start/end range
+    #[serde(default)]
+    ports: String,
+    /// Timing template; the optional fields below override single values of it
+    #[serde(default)]
+    timing: TimingTemplate,
+    /// Maximum number of ports probed at the same time; empty or 0 keeps the template's
+    #[serde(default, alias = "threads", deserialize_with = "optional_limit")]
+    concurrency: Option<usize>,
+    #[serde(default, deserialize_with = "optional_limit")]
+    timeout_ms: Option<u64>,
+    #[serde(default, deserialize_with = "optional_limit")]
+    udp_timeout_ms: Option<u64>,
+    #[serde(default, deserialize_with = "optional_limit")]
+    probe_timeout_ms: Option<u64>,
+    /// Optional probes-per-second limit; empty keeps the template's, 0 lifts it
+    #[serde(default, deserialize_with = "optional_number")]
+    max_rate: Option<u32>,
+    /// Optional per-host concurrency limit; empty or 0 means unlimited
+    #[serde(default, deserialize_with = "optional_limit")]
+    max_per_host: Option<usize>,
+    /// Resends for probes that timed out; empty keeps the template's
+    #[serde(default, deserialize_with = "optional_number")]
+    retries: Option<u32>,
+    #[serde(default)]
+    ip_family: IpFamily,
+    /// Checkboxes selecting the protocols to scan; neither means both
+    #[serde(default)]
+    tcp: Option<String>,
+    #[serde(default)]
+    udp: Option<String>,
+    /// Checkbox: present (as "on") when closed/filtered ports should be listed
+    #[serde(default)]
+    show_closed: Option<String>,
+    /// Checkbox: present when TCP ports should be SYN scanned
+    #[serde(default)]
+    syn: Option<String>,
+    /// Checkbox: present when every host should be scanned without pinging it first
+    #[serde(default)]
+    skip_discovery: Option<String>,
+    /// Checkbox: present when ports should be probed in a random order
+    #[serde(default)]
+    randomize: Option<String>,
+    /// Checkbox: present when hosts should take turns
+    #[serde(default)]
+    interleave: Option<String>,
+    /// Seed of an earlier randomized scan, to repeat its order; implies randomize
+    #[serde(default, deserialize_with = "optional_number")]
+    seed: Option<u64>,
+}
+
+/// Parses an optional numeric form field, treating an empty field as unset
+fn optional_number<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = String::deserialize(deserializer)?;
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+/// Parses an optional numeric form field, treating an empty field or 0 as unset
+fn optional_limit<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr + Default + PartialEq,
+    T::Err: std::fmt::Display,
+{
+    Ok(optional_number::<D, T>(deserializer)?.filter(|limit| *limit != T::default()))
+}
+
+/// Escapes text for HTML: targets and errors echo user input, and banners come
+/// straight from the scanned services
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
 }
 
-pub async fn run_web_server(matcher: Arc<SignatureMatcher>) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn run_web_server(matcher: Arc<SignatureMatcher>, addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
     let app_state = AppState { matcher };
 
     let app = Router::new()
         .route("/", get(serve_index))
         .route("/styles.css", get(serve_styles))
         .route("/api/scan", post(start_scan))
+        .route("/api/scan/cancel", post(cancel_scan))
         .route("/api/status", get(get_status))
         .route("/api/results", get(get_results))
         .with_state(app_state);
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:9876").await?;
-    println!("\n🌐 Web UI running at http://127.0.0.1:9876\n");
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    println!("\n🌐 Web UI running at http://{}\n", listener.local_addr()?);
 
     axum::serve(listener, app).await?;
     Ok(())
@@ -64,26 +157,61 @@ async fn start_scan(
     State(app_state): State<AppState>,
     Form(req): Form<ScanRequest>,
 ) -> Html<String> {
-    // Create new scan state
-    let scan_state = ScanState::new(
-        req.target.clone(),
-        req.start_port,
-        req.end_port,
-        req.threads,
-        req.timeout_ms,
-        req.udp_timeout_ms,
-    );
-
+    let builder = Scanner::builder()
+        .targets(&req.target)
+        .ip_family(req.ip_family)
+        .protocols(req.tcp.is_some(), req.udp.is_some())
+        .timing(req.timing.timing().with_overrides(&TimingOverrides {
+            timeout_ms: req.timeout_ms,
+            udp_timeout_ms: req.udp_timeout_ms,
+            probe_timeout_ms: req.probe_timeout_ms,
+            retries: req.retries,
+            concurrency: req.concurrency,
+            max_rate: req.max_rate.map(|rate| (rate > 0).then_some(rate)),
+            ..Default::default()
+        }))
+        .max_per_host(req.max_per_host)
+        .show_closed(req.show_closed.is_some())
+        .syn_scan(req.syn.is_some())
+        .skip_discovery(req.skip_discovery.is_some())
+        .randomize(req.randomize.is_some())
+        .seed(req.seed)
+        .interleave(req.interleave.is_some())
+        .signatures(app_state.matcher.clone());
+    let builder = if req.ports.trim().is_empty() {
+        if req.start_port == 0 {
+            return Html(r#"<div id="progress-container"><p>Start port must be at least 1</p></div>"#.to_string());
+        }
+        if req.end_port < req.start_port {
+            return Html(r#"<div id="progress-container"><p>End port must not be below start port</p></div>"#.to_string());
+        }
+        builder.ports(PortSpec::range(req.start_port, req.end_port))
+    } else {
+        builder.port_spec(&req.ports)
+    };
+
+    // Building resolves every host, and DNS lookups block, so keep it off the async runtime
+    let scanner = match tokio::task::spawn_blocking(move || builder.build()).await {
+        Ok(Ok(scanner)) => scanner,
+        Ok(Err(e)) => return Html(format!(r#"<div id="progress-container"><p>{}</p></div>"#, escape(&e))),
+        Err(e) => return Html(format!(r#"<div id="progress-container"><p>Scan setup failed: {}</p></div>"#, escape(&e.to_string()))),
+    };
+
+    // Create new scan state; it collects the results as the scan's sink
+    let scan_state = ScanState::new(req.target.clone(), &scanner);
     scan_state.start();
 
-    // Store in global state
-    *CURRENT_SCAN.write().unwrap() = Some(scan_state.clone());
+    // Store in global state, stopping any scan it replaces
+    if let Some(previous) = CURRENT_SCAN.write().unwrap().replace(scan_state.clone()) {
+        previous.cancel();
+    }
 
-    // Spawn scan in background thread
-    let matcher = app_state.matcher.clone();
-    thread::spawn(move || {
-        run_scan(scan_state, matcher);
-    });
+    // Run the scan in the background, echoing open ports to the terminal
+    let scan = scanner
+        .with_sink(Arc::new(scan_state))
+        .with_sink(Arc::new(PrettySink::stdout()))
+        .start();
+    tokio::spawn(scan.finish());
 
     Html(r#"
         <div id="progress-container">
@@ -99,51 +227,15 @@ async fn start_scan(
     "#.to_string())
 }
 
-fn run_scan(scan_state: ScanState, matcher: Arc<SignatureMatcher>) {
-    let (task_tx, task_rx) = mpsc::channel();
-    let (res_tx, res_rx) = mpsc::channel();
-
-    let task_rx = Arc::new(Mutex::new(task_rx));
-    let completed = scan_state.scanned_count.clone();
-    let target = Arc::new(scan_state.target.clone());
-
-    // Spawn worker threads
-    for _ in 0..scan_state.threads {
-        let task_rx_clone = Arc::clone(&task_rx);
-        let res_tx_clone = res_tx.clone();
-        let target_clone = Arc::clone(&target);
-        let completed_clone = Arc::clone(&completed);
-        let matcher_clone = Arc::clone(&matcher);
-        let timeout = scan_state.timeout_ms;
-        let udp_timeout = scan_state.udp_timeout_ms;
-
-        thread::spawn(move || {
-            worker_loop(
-                task_rx_clone,
-                res_tx_clone,
-                target_clone,
-                completed_clone,
-                timeout,
-                udp_timeout,
-                matcher_clone,
-            );
-        });
-    }
-
-    // Send all ports to workers
-    for port in scan_state.start_port..=scan_state.end_port {
-        let _ = task_tx.send(port);
-    }
-    drop(task_tx); // Close channel so workers know to stop
+async fn cancel_scan() -> Html<String> {
+    let scan = CURRENT_SCAN.read().unwrap();
 
-    // Collect results
-    drop(res_tx); // Close sender so receiver knows when to stop
-    for service_info in res_rx {
-        scan_state.add_result(service_info);
+    match *scan {
+        Some(ref state) if state.cancel() => {
+            Html(format!("<p>Cancelling scan of {}...</p>", escape(&state.target)))
+        }
+        _ => Html("<p>No running scan</p>".to_string()),
     }
-
-    // Mark scan as complete
-    scan_state.complete();
 }
 
 async fn get_status() -> Html<String> {
@@ -157,30 +249,67 @@ async fn get_status() -> Html<String> {
             0
         };
 
-        let status_text = if state.is_complete() {
-            format!("Scan complete! {}/{} ports scanned", scanned, total)
-        } else if state.is_running() {
-            format!("Scanning... {}/{} ports ({:.0}%)", scanned, total, percentage)
-        } else {
-            "Idle".to_string()
+        let target = escape(&state.target);
+        let status_text = match state.status() {
+            ScanStatus::Complete => {
+                format!("Scan of {} complete! {}/{} ports scanned across {} host(s)", target, scanned, total, state.hosts.len())
+            }
+            ScanStatus::Cancelled => {
+                format!("Scan of {} cancelled after {}/{} ports across {} host(s); results are partial", target, scanned, total, state.hosts.len())
+            }
+            ScanStatus::Cancelling => {
+                format!("Cancelling scan of {}... waiting for in-flight probes ({}/{} ports)", target, scanned, total)
+            }
+            ScanStatus::Running => {
+                format!(
+                    r#"Scanning {} ({} host(s))... {}/{} ports ({:.0}%) <button class="cancel-button" hx-post="/api/scan/cancel" hx-swap="none">Stop</button>"#,
+                    target, state.hosts.len(), scanned, total, percentage
+                )
+            }
+            ScanStatus::Pending => "Idle".to_string(),
         };
 
         // Only poll if scan is still running
-        let (poll_trigger, results_update) = if state.is_complete() {
+        let (poll_trigger, results_update) = if state.is_finished() {
             (String::new(), r#"<script>htmx.trigger('#results-container', 'scan-complete')</script>"#)
         } else {
             (r#" hx-get="/api/status" hx-trigger="every 500ms" hx-swap="outerHTML""#.to_string(), "")
         };
 
+        let mut dns_note = if state.dns_errors.is_empty() {
+            String::new()
+        } else {
+            format!("<p>Skipped unresolvable hosts: {}</p>", escape(&state.dns_errors.join("; ")))
+        };
+        let discovery = state.get_discovery();
+        if !discovery.is_empty() {
+            let down: Vec<String> = discovery
+                .iter()
+                .filter(|status| !status.up)
+                .map(|status| format!("{} ({})", escape(&status.host), status.ip))
+                .collect();
+            dns_note.push_str(&format!("<p>Hosts up: {}, down: {}</p>", discovery.len() - down.len(), down.len()));
+            if !down.is_empty() {
+                dns_note.push_str(&format!(
+                    "<p>Not scanned, no answer to discovery: {}. Tick Skip host discovery to scan them anyway.</p>",
+                    down.join(", ")
+                ));
+            }
+        }
+        if let Some(seed) = state.options.order.seed {
+            dns_note.push_str(&format!("<p>Probing in random order, seed {} (enter it as the seed to repeat this order)</p>", seed));
+        }
+
         Html(format!(r#"
             <div{}>
                 <div class="progress-bar">
                     <div class="progress-fill" style="width: {}%"></div>
                 </div>
                 <p>{}</p>
+                {}
             </div>
             {}
-        "#, poll_trigger, percentage, status_text, results_update))
+        "#, poll_trigger, percentage, status_text, dns_note, results_update))
     } else {
         Html(r#"
             <div>
@@ -197,7 +326,7 @@ async fn get_results() -> Html<String> {
         let results = state.get_results();
 
         if results.is_empty() {
-            let poll_attr = if state.is_complete() {
+            let poll_attr = if state.is_finished() {
                 String::new()
             } else {
                 r#" hx-get="/api/results" hx-trigger="every 1s" hx-swap="outerHTML""#.to_string()
@@ -206,7 +335,7 @@ async fn get_results() -> Html<String> {
         }
 
         // Only poll if scan is still running
-        let poll_attr = if state.is_complete() {
+        let poll_attr = if state.is_finished() {
             String::new()
         } else {
             r#" hx-get="/api/results" hx-trigger="every 1s" hx-swap="outerHTML""#.to_string()
@@ -217,6 +346,7 @@ async fn get_results() -> Html<String> {
             <table class="results-table">
                 <thead>
                     <tr>
+                        <th>Host</th>
                         <th>Port</th>
                         <th>Protocol</th>
                         <th>State</th>
@@ -229,35 +359,83 @@ async fn get_results() -> Html<String> {
                 <tbody>
         "#);
 
-        for result in results {
-            let confidence_class = if result.confidence > 0.8 {
-                "high"
-            } else if result.confidence > 0.5 {
-                "medium"
-            } else {
-                "low"
-            };
+        for (host, host_results) in group_by_host(&state.addresses, &results) {
+            if host_results.is_empty() {
+                continue;
+            }
+
+            let count = |state: PortState| host_results.iter().filter(|r| r.state == state).count();
+            let mut summary = format!("{} open", count(PortState::Open));
+            if state.options.show_closed {
+                summary.push_str(&format!(", {} closed, {} filtered", count(PortState::Closed), count(PortState::Filtered)));
+            }
+            let open_filtered = count(PortState::OpenFiltered);
+            if open_filtered > 0 {
+                summary.push_str(&format!(", {} open|filtered", open_filtered));
+                if !state.options.show_closed {
+                    summary.push_str(" (hidden)");
+                }
+            }
+            if let Some(estimate) = host_results[0].ip.and_then(|ip| state.rtt.estimate(ip)) {
+                summary.push_str(&format!(", {}", estimate));
+            }
 
             html.push_str(&format!(r#"
-                <tr>
-                    <td><strong>{}</strong></td>
-                    <td>{}</td>
-                    <td><span class="state-open">{}</span></td>
-                    <td>{}</td>
-                    <td>{}</td>
-                    <td class="banner">{}</td>
-                    <td><span class="confidence {}">{:.0}%</span></td>
+                <tr class="host-row">
+                    <td colspan="8"><strong>{}</strong> &mdash; {}</td>
                 </tr>
-            "#,
-                result.port,
-                result.protocol,
-                result.state,
-                result.service.as_deref().unwrap_or("unknown"),
-                result.version.as_deref().unwrap_or("-"),
-                result.banner.as_deref().unwrap_or("-"),
-                confidence_class,
-                result.confidence * 100.0
-            ));
+            "#, escape(&host), summary));
+
+            // Silent UDP ports are not conclusive, so only list them on request
+            let visible = host_results
+                .into_iter()
+                .filter(|r| r.state != PortState::OpenFiltered || state.options.show_closed);
+
+            for result in visible {
+                let confidence_class = if result.confidence > 0.8 {
+                    "high"
+                } else if result.confidence > 0.5 {
+                    "medium"
+                } else {
+                    "low"
+                };
+                let state_class = match result.state {
+                    PortState::Open => "state-open",
+                    PortState::Closed => "state-closed",
+                    PortState::Filtered | PortState::OpenFiltered => "state-filtered",
+                };
+                let mut reason = result.reason.as_deref()
+                    .map(|r| format!(r#" <small class="reason">{}</small>"#, escape(r)))
+                    .unwrap_or_default();
+                if result.retries > 0 {
+                    reason.push_str(&format!(r#" <small class="reason">{} retries</small>"#, result.retries));
+                }
+
+                html.push_str(&format!(r#"
+                    <tr>
+                        <td>{}</td>
+                        <td><strong>{}</strong></td>
+                        <td>{}</td>
+                        <td><span class="{}">{}</span>{}</td>
+                        <td>{}</td>
+                        <td>{}</td>
+                        <td class="banner">{}</td>
+                        <td><span class="confidence {}">{:.0}%</span></td>
+                    </tr>
+                "#,
+                    escape(&result.host),
+                    result.port,
+                    result.protocol,
+                    state_class,
+                    result.state,
+                    reason,
+                    escape(result.service.as_deref().unwrap_or(if result.state == PortState::Open { "unknown" } else { "-" })),
+                    escape(result.version.as_deref().unwrap_or("-")),
+                    escape(result.banner.as_deref().unwrap_or("-")),
+                    confidence_class,
+                    result.confidence * 100.0
+                ));
+            }
         }
 
         html.push_str("</tbody></table></div>");
@@ -266,3 +444,13 @@ async fn get_results() -> Html<String> {
         Html(r#"<div id="results-container"><p>No scan data available</p></div>"#.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape(r#"Invalid target: <script>alert("x")</script> & 'y'"#), "Invalid target: &lt;script&gt;alert(&quot;x&quot;)&lt;/script&gt; &amp; &#39;y&#39;");
+    }
+}
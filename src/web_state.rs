@@ -1,65 +1,94 @@
-use crate::service_info::ServiceInfo;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use port_scanner::rtt::RttTable;
+use port_scanner::scanner::ScanOptions;
+use port_scanner::{HostStatus, ResultSink, ScanEvent, Scanner, ServiceInfo};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+/// Lifecycle of a web UI scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanStatus {
+    Pending,
+    Running,
+    /// Cancel was requested; in-flight probes are still draining
+    Cancelling,
+    Complete,
+    /// Stopped early; the results collected so far are kept
+    Cancelled,
+}
 
 #[derive(Clone)]
 pub struct ScanState {
     pub target: String,
-    pub start_port: u16,
-    pub end_port: u16,
-    pub threads: usize,
-    pub timeout_ms: u64,
-    pub udp_timeout_ms: u64,
+    pub hosts: Vec<String>,
+    pub addresses: Vec<(String, IpAddr)>,
+    pub dns_errors: Vec<String>,
+    pub options: ScanOptions,
+    /// Per-host round-trip times measured during the scan
+    pub rtt: Arc<RttTable>,
     pub scanned_count: Arc<AtomicUsize>,
+    /// Number of TCP and UDP probes the scan will send
     pub total_ports: usize,
     pub results: Arc<Mutex<Vec<ServiceInfo>>>,
-    pub is_running: Arc<AtomicBool>,
-    pub is_complete: Arc<AtomicBool>,
+    /// Host discovery results, as hosts are pinged
+    pub discovery: Arc<Mutex<Vec<HostStatus>>>,
+    pub status: Arc<Mutex<ScanStatus>>,
+    /// Stops the scan engine from sending new probes
+    pub cancel: CancellationToken,
 }
 
 impl ScanState {
-    pub fn new(
-        target: String,
-        start_port: u16,
-        end_port: u16,
-        threads: usize,
-        timeout_ms: u64,
-        udp_timeout_ms: u64,
-    ) -> Self {
-        let total_ports = (end_port - start_port + 1) as usize;
+    /// Tracks `scanner`; add the state to it as a sink to collect its results
+    pub fn new(target: String, scanner: &Scanner) -> Self {
         Self {
             target,
-            start_port,
-            end_port,
-            threads,
-            timeout_ms,
-            udp_timeout_ms,
-            scanned_count: Arc::new(AtomicUsize::new(0)),
-            total_ports,
+            hosts: scanner.hosts(),
+            addresses: scanner.addresses(),
+            dns_errors: scanner.dns_errors().to_vec(),
+            options: scanner.options().clone(),
+            rtt: scanner.rtt(),
+            scanned_count: scanner.completed(),
+            total_ports: scanner.total_probes(),
             results: Arc::new(Mutex::new(Vec::new())),
-            is_running: Arc::new(AtomicBool::new(false)),
-            is_complete: Arc::new(AtomicBool::new(false)),
+            discovery: Arc::new(Mutex::new(Vec::new())),
+            status: Arc::new(Mutex::new(ScanStatus::Pending)),
+            cancel: scanner.cancel_token(),
         }
     }
 
     pub fn start(&self) {
-        self.is_running.store(true, Ordering::SeqCst);
-        self.is_complete.store(false, Ordering::SeqCst);
-        self.scanned_count.store(0, Ordering::SeqCst);
+        *self.status.lock().unwrap() = ScanStatus::Running;
         self.results.lock().unwrap().clear();
+        self.discovery.lock().unwrap().clear();
     }
 
+    /// Marks the scan as finished, as cancelled if a cancel was requested
     pub fn complete(&self) {
-        self.is_running.store(false, Ordering::SeqCst);
-        self.is_complete.store(true, Ordering::SeqCst);
+        let mut status = self.status.lock().unwrap();
+        *status = match *status {
+            ScanStatus::Cancelling | ScanStatus::Cancelled => ScanStatus::Cancelled,
+            _ => ScanStatus::Complete,
+        };
+    }
+
+    /// Asks a running scan to stop; returns false if it was not running
+    pub fn cancel(&self) -> bool {
+        let mut status = self.status.lock().unwrap();
+        if *status != ScanStatus::Running {
+            return false;
+        }
+        *status = ScanStatus::Cancelling;
+        self.cancel.cancel();
+        true
     }
 
     pub fn add_result(&self, result: ServiceInfo) {
         self.results.lock().unwrap().push(result);
     }
 
-    pub fn increment_scanned(&self) {
-        self.scanned_count.fetch_add(1, Ordering::SeqCst);
+    pub fn get_discovery(&self) -> Vec<HostStatus> {
+        self.discovery.lock().unwrap().clone()
     }
 
     pub fn get_progress(&self) -> (usize, usize) {
@@ -70,11 +99,46 @@ impl ScanState {
         self.results.lock().unwrap().clone()
     }
 
-    pub fn is_running(&self) -> bool {
-        self.is_running.load(Ordering::SeqCst)
+    pub fn status(&self) -> ScanStatus {
+        *self.status.lock().unwrap()
+    }
+
+    /// True once the scan engine has stopped, whether it completed or was cancelled
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status(), ScanStatus::Complete | ScanStatus::Cancelled)
     }
+}
+
+impl ResultSink for ScanState {
+    fn handle(&self, event: &ScanEvent) {
+        match event {
+            ScanEvent::Service(info) => self.add_result(info.clone()),
+            ScanEvent::Discovery(status) => self.discovery.lock().unwrap().push(status.clone()),
+            ScanEvent::ScanDone { .. } => self.complete(),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_flips_status() {
+        let scanner = Scanner::builder().targets("127.0.0.1").port_spec("1-10").protocols(true, false).build().unwrap();
+        let state = ScanState::new("127.0.0.1".to_string(), &scanner);
+        assert!(!state.cancel(), "a scan that has not started cannot be cancelled");
+
+        state.start();
+        assert!(state.cancel());
+        assert_eq!(state.status(), ScanStatus::Cancelling);
+        assert!(state.cancel.is_cancelled());
+        assert!(!state.is_finished());
 
-    pub fn is_complete(&self) -> bool {
-        self.is_complete.load(Ordering::SeqCst)
+        state.complete();
+        assert_eq!(state.status(), ScanStatus::Cancelled);
+        assert!(state.is_finished());
+        assert!(!state.cancel());
     }
 }
@@ -0,0 +1,239 @@
+//! Scans through small SOCKS5 and HTTP CONNECT proxies running in the test. The
+//! proxies only know one route, from an address that does not exist locally to an
+//! HTTP server on loopback, so every result must have gone through them.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use port_scanner::scan::DEFAULT_SIGNATURES;
+use port_scanner::{PortState, Proxy, Scanner, ServiceInfo, SignatureMatcher, TimingTemplate};
+
+/// The scanned address; documentation range, so a direct connect goes nowhere
+const TARGET: &str = "198.51.100.7";
+const OPEN_PORT: u16 = 8080;
+
+/// What the proxy was asked for: the destination for SOCKS5, the request line for HTTP
+type Seen = Arc<Mutex<Vec<String>>>;
+
+fn spawn_http_backend() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut request = String::new();
+            let mut reader = BufReader::new(&stream);
+            while reader.read_line(&mut request).map(|n| n > 2).unwrap_or(false) {}
+            let _ = (&stream).write_all(b"HTTP/1.1 200 OK\r\nServer: nginx/1.25.3\r\nContent-Length: 0\r\n\r\n");
+        }
+    });
+    addr
+}
+
+/// Where the proxy sends a connection for `dest`, if anywhere
+fn route(dest: SocketAddr, backend: SocketAddr) -> Option<SocketAddr> {
+    (dest == SocketAddr::new(TARGET.parse().unwrap(), OPEN_PORT)).then_some(backend)
+}
+
+fn relay(client: TcpStream, upstream: TcpStream) {
+    let (mut client_read, mut upstream_write) = (client.try_clone().unwrap(), upstream.try_clone().unwrap());
+    thread::spawn(move || {
+        let _ = io::copy(&mut client_read, &mut upstream_write);
+        let _ = upstream_write.shutdown(std::net::Shutdown::Write);
+    });
+    let (mut upstream, mut client) = (upstream, client);
+    let _ = io::copy(&mut upstream, &mut client);
+}
+
+fn spawn_socks5_proxy(backend: SocketAddr, seen: Seen) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let seen = Arc::clone(&seen);
+            thread::spawn(move || {
+                let _ = socks5_session(stream, backend, &seen);
+            });
+        }
+    });
+    addr
+}
+
+fn socks5_session(mut client: TcpStream, backend: SocketAddr, seen: &Seen) -> io::Result<()> {
+    let mut header = [0u8; 2];
+    client.read_exact(&mut header)?;
+    let mut methods = vec![0u8; usize::from(header[1])];
+    client.read_exact(&mut methods)?;
+    if !methods.contains(&0x02) {
+        return client.write_all(&[5, 0xff]);
+    }
+    client.write_all(&[5, 2])?;
+
+    // Username/password authentication (RFC 1929)
+    let field = |client: &mut TcpStream| -> io::Result<String> {
+        let mut len = [0u8; 1];
+        client.read_exact(&mut len)?;
+        let mut value = vec![0u8; usize::from(len[0])];
+        client.read_exact(&mut value)?;
+        Ok(String::from_utf8_lossy(&value).into_owned())
+    };
+    let mut version = [0u8; 1];
+    client.read_exact(&mut version)?;
+    let credentials = format!("{}:{}", field(&mut client)?, field(&mut client)?);
+    if credentials != "scanner:hunter2" {
+        return client.write_all(&[1, 1]);
+    }
+    client.write_all(&[1, 0])?;
+
+    let mut request = [0u8; 4];
+    client.read_exact(&mut request)?;
+    assert_eq!(request[3], 0x01, "the scanner connects by IPv4 address");
+    let mut dest = [0u8; 6];
+    client.read_exact(&mut dest)?;
+    let dest = SocketAddr::from(([dest[0], dest[1], dest[2], dest[3]], u16::from_be_bytes([dest[4], dest[5]])));
+    seen.lock().unwrap().push(dest.to_string());
+
+    match route(dest, backend).map(TcpStream::connect) {
+        Some(Ok(upstream)) => {
+            client.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])?;
+            relay(client, upstream);
+        }
+        // Connection refused
+        _ => client.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0])?,
+    }
+    Ok(())
+}
+
+fn spawn_http_proxy(backend: SocketAddr, seen: Seen) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let seen = Arc::clone(&seen);
+            thread::spawn(move || {
+                let _ = http_connect_session(stream, backend, &seen);
+            });
+        }
+    });
+    addr
+}
+
+fn http_connect_session(mut client: TcpStream, backend: SocketAddr, seen: &Seen) -> io::Result<()> {
+    // Read the request byte by byte so nothing after the header is lost
+    let mut header = Vec::new();
+    let mut byte = [0u8; 1];
+    while !header.ends_with(b"\r\n\r\n") {
+        client.read_exact(&mut byte)?;
+        header.push(byte[0]);
+    }
+    let header = String::from_utf8_lossy(&header).into_owned();
+    let request_line = header.lines().next().unwrap_or_default().to_string();
+    seen.lock().unwrap().push(request_line.clone());
+
+    let dest = request_line.split_whitespace().nth(1).and_then(|dest| dest.parse().ok());
+    match dest.and_then(|dest| route(dest, backend)).map(TcpStream::connect) {
+        Some(Ok(upstream)) => {
+            client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+            relay(client, upstream);
+        }
+        _ => client.write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n")?,
+    }
+    Ok(())
+}
+
+async fn scan_through(proxy: Proxy) -> Vec<ServiceInfo> {
+    let scanner = Scanner::builder()
+        .targets(TARGET)
+        .port_spec(&format!("22,{}", OPEN_PORT))
+        .timing_template(TimingTemplate::Aggressive)
+        .show_closed(true)
+        .proxy(Some(proxy))
+        .build()
+        .unwrap();
+    // Without an explicit protocol choice a proxied scan is TCP only
+    assert_eq!(scanner.total_probes(), 2);
+
+    let mut scan = scanner.start();
+    let mut results = Vec::new();
+    while let Some(info) = scan.next().await {
+        results.push(info);
+    }
+    scan.finish().await.unwrap();
+    results.sort_by_key(|r| r.port);
+    results
+}
+
+fn assert_scanned_through_proxy(results: &[ServiceInfo]) {
+    assert_eq!(results.len(), 2, "{:?}", results);
+    assert_eq!((results[0].port, &results[0].state), (22, &PortState::Closed));
+    assert_eq!((results[1].port, &results[1].state), (OPEN_PORT, &PortState::Open));
+    // Fingerprinting went through the proxy too
+    assert_eq!(results[1].banner.as_deref(), Some("HTTP/1.1 200 OK"));
+}
+
+#[tokio::test]
+async fn test_scan_through_socks5_proxy() {
+    let seen = Seen::default();
+    let proxy = spawn_socks5_proxy(spawn_http_backend(), Arc::clone(&seen));
+
+    let results = scan_through(format!("socks5://scanner:hunter2@{}", proxy).parse().unwrap()).await;
+    assert_scanned_through_proxy(&results);
+    assert!(seen.lock().unwrap().contains(&format!("{}:22", TARGET)));
+
+    // A wrong password stops the scan before it starts, instead of every port looking unreachable
+    let err = Scanner::builder()
+        .targets(TARGET)
+        .port_spec("22")
+        .proxy(Some(format!("socks5://scanner:wrong@{}", proxy).parse().unwrap()))
+        .build()
+        .err()
+        .unwrap();
+    assert!(err.contains("proxy rejected the credentials"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_resuming_needs_the_proxy_password_again() {
+    let proxy = spawn_socks5_proxy(spawn_http_backend(), Seen::default());
+    let url = format!("socks5://scanner:hunter2@{}", proxy);
+    let path = std::env::temp_dir().join(format!("port-scanner-proxy-checkpoint-{}.json", std::process::id()));
+    let scanner = Scanner::builder()
+        .targets(TARGET)
+        .port_spec("22")
+        .proxy(Some(url.parse().unwrap()))
+        .checkpoint(path.clone())
+        .build()
+        .unwrap();
+    scanner.start().finish().await.unwrap();
+    let matcher = Arc::new(SignatureMatcher::load(DEFAULT_SIGNATURES).unwrap());
+
+    let saved = std::fs::read_to_string(&path).unwrap();
+    let without_password = Scanner::resume(path.clone(), Arc::clone(&matcher), None).err();
+    let other_proxy = Scanner::resume(path.clone(), Arc::clone(&matcher), Some("http://127.0.0.1:1".parse().unwrap())).err();
+    let with_password = Scanner::resume(path.clone(), matcher, Some(url.parse().unwrap()));
+    let _ = std::fs::remove_file(&path);
+
+    assert!(!saved.contains("hunter2"));
+    assert!(without_password.unwrap().contains("needs its credentials again"));
+    assert!(other_proxy.unwrap().contains("not http://127.0.0.1:1"));
+    assert!(with_password.is_ok());
+}
+
+#[tokio::test]
+async fn test_scan_through_http_connect_proxy() {
+    let seen = Seen::default();
+    let proxy = spawn_http_proxy(spawn_http_backend(), Arc::clone(&seen));
+
+    let results = scan_through(format!("http://{}", proxy).parse().unwrap()).await;
+    assert_scanned_through_proxy(&results);
+    assert!(seen.lock().unwrap().contains(&format!("CONNECT {}:{} HTTP/1.1", TARGET, OPEN_PORT)));
+}
+
+#[test]
+fn test_proxied_scans_are_tcp_only() {
+    let proxy: Proxy = "socks5://127.0.0.1:1".parse().unwrap();
+    let err = Scanner::builder().port_spec("53").protocols(true, true).proxy(Some(proxy.clone())).build().err().unwrap();
+    assert_eq!(err, "UDP ports cannot be scanned through a proxy");
+    let err = Scanner::builder().port_spec("80").syn_scan(true).proxy(Some(proxy)).build().err().unwrap();
+    assert_eq!(err, "SYN scans cannot go through a proxy");
+}